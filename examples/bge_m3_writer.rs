@@ -0,0 +1,109 @@
+use rustserini::encode::base::RepresentationWriter;
+use rustserini::encode::bge_m3::BgeM3Encoder;
+use rustserini::encode::config::EncoderConfig;
+use rustserini::encode::source::ModelSource;
+use rustserini::encode::vector_writer::{
+    ColBertIndexWriter, FaissRepresentationWriter, JsonVectorCollectionWriter, JsonlCollectionIterator, M3RepresentationWriter,
+};
+use std::time::Instant;
+use clap::{ArgAction, Parser};
+
+
+/// Simple program to encode a corpus with BGE-M3 into a dense Faiss index, a JsonVectorCollection and a
+/// multi-vector index in a single pass, any of the three outputs can be left out
+/// cargo run --example bge_m3_writer -- --corpus corpus/msmarco-passage/corpus.jsonl --dense-dir indexes/msmarco-passage-bge-m3 --sparse-dir corpus/msmarco-passage-bge-m3-sparse --multi-vector-dir indexes/msmarco-passage-bge-m3-colbert
+
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Directory that contains corpus files to be encoded, in jsonl format.
+    #[arg(short, long)]
+    corpus: String,
+
+    /// Fields that contents in jsonl has (in order) separated by comma.
+    #[arg(short, long, default_value = "text")]
+    fields: String,
+
+    /// directory to store the dense Faiss index
+    #[arg(long)]
+    dense_dir: Option<String>,
+
+    /// directory to store the JsonVectorCollection of lexical weights
+    #[arg(long)]
+    sparse_dir: Option<String>,
+
+    /// directory to store the multi-vector index
+    #[arg(long)]
+    multi_vector_dir: Option<String>,
+
+    /// Encoder name or path
+    #[arg(long, default_value = "BAAI/bge-m3")]
+    encoder: String,
+
+    /// Encoder Revision
+    #[arg(long, default_value = "main")]
+    revision: String,
+
+    /// Only use files from the local Hugging Face cache, never contacting the hub
+    #[arg(long, action=ArgAction::SetTrue)]
+    offline: bool,
+
+    /// Batch size for encoding
+    #[arg(short, long, default_value_t = 16)]
+    batch_size: usize,
+
+    /// max length of the input
+    #[arg(short, long, default_value_t = 512)]
+    max_length: usize,
+
+    /// Faiss index_factory description of the dense index
+    #[arg(long, default_value = "Flat")]
+    index_type: String,
+
+    /// Factor the lexical weights are multiplied by before rounding them to integers
+    #[arg(long, default_value_t = 100.0)]
+    quantization_factor: f32,
+}
+
+fn main() -> anyhow::Result<()> {
+    let start = Instant::now();
+    let args = Args::parse();
+
+    let fields: Vec<String> = args.fields.split(',').map(|s| s.to_string()).collect();
+    let mut iterator: JsonlCollectionIterator =
+        JsonlCollectionIterator::new(fields, "id".to_string(), "\n".to_string(), args.batch_size);
+    iterator.load(args.corpus)?;
+
+    let source = ModelSource::new(&args.encoder, &args.revision).offline(args.offline);
+    let config = EncoderConfig::new(args.max_length);
+    let encoder = BgeM3Encoder::from_source(&source)?.with_config(config.clone())?;
+
+    let mut writer = M3RepresentationWriter::new();
+    if let Some(dense_dir) = &args.dense_dir {
+        let dimension = encoder.dense_dimension()? as u32;
+        let mut dense = FaissRepresentationWriter::new(dense_dir, dimension)?;
+        dense.init_index(dimension, &args.index_type)?;
+        config.save(dense_dir)?;
+        writer = writer.with_dense(dense);
+    }
+    if let Some(sparse_dir) = &args.sparse_dir {
+        writer = writer.with_sparse(JsonVectorCollectionWriter::new(sparse_dir).with_quantization_factor(args.quantization_factor));
+    }
+    if let Some(multi_vector_dir) = &args.multi_vector_dir {
+        writer = writer.with_multi_vector(ColBertIndexWriter::new(multi_vector_dir, encoder.multi_vector_dimension()? as u32)?);
+        config.save(multi_vector_dir)?;
+    }
+    writer.open_file()?;
+
+    for batch in iterator.iter() {
+        let embeddings = encoder.encode_documents(&batch["text"], batch.get("title"))?;
+        writer.write(&batch, &embeddings)?;
+    }
+    writer.save()?;
+
+    let duration = start.elapsed();
+    println!("Time elapsed in expensive_function() is: {:?}", duration);
+
+    Ok(())
+}
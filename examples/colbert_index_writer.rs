@@ -0,0 +1,87 @@
+use rustserini::encode::colbert::{ColBertConfig, ColBertEncoder};
+use rustserini::encode::config::EncoderConfig;
+use rustserini::encode::source::ModelSource;
+use rustserini::encode::vector_writer::{ColBertIndexWriter, JsonlCollectionIterator};
+use std::time::Instant;
+use clap::{ArgAction, Parser};
+
+
+/// Simple program to encode a corpus into a ColBERT multi-vector index
+/// The index can then be searched with rustserini::searcher::faiss::colbert::ColBertSearcher
+/// cargo run --example colbert_index_writer -- --corpus corpus/msmarco-passage/corpus.jsonl --index-dir indexes/msmarco-passage-colbertv2 --encoder colbert-ir/colbertv2.0
+
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Directory that contains corpus files to be encoded, in jsonl format.
+    #[arg(short, long)]
+    corpus: String,
+
+    /// Fields that contents in jsonl has (in order) separated by comma.
+    #[arg(short, long, default_value = "text")]
+    fields: String,
+
+    /// directory to store the index
+    #[arg(short, long, required = true)]
+    index_dir: String,
+
+    /// Encoder name or path
+    #[arg(long, default_value = "colbert-ir/colbertv2.0")]
+    encoder: String,
+
+    /// Encoder Revision
+    #[arg(long, default_value = "main")]
+    revision: String,
+
+    /// Only use files from the local Hugging Face cache, never contacting the hub
+    #[arg(long, action=ArgAction::SetTrue)]
+    offline: bool,
+
+    /// Batch size for encoding
+    #[arg(short, long, default_value_t = 32)]
+    batch_size: usize,
+
+    /// Maximum number of query tokens, queries are padded with [MASK] up to it
+    #[arg(long, default_value_t = 32)]
+    query_max_length: usize,
+
+    /// Maximum number of document tokens
+    #[arg(long, default_value_t = 180)]
+    doc_max_length: usize,
+
+    /// Faiss index_factory description of the candidate generation index
+    #[arg(long, default_value = "Flat")]
+    index_type: String,
+}
+
+fn main() -> anyhow::Result<()> {
+    let start = Instant::now();
+    let args = Args::parse();
+
+    let fields: Vec<String> = args.fields.split(',').map(|s| s.to_string()).collect();
+    let mut iterator: JsonlCollectionIterator =
+        JsonlCollectionIterator::new(fields, "id".to_string(), "\n".to_string(), args.batch_size);
+    iterator.load(args.corpus)?;
+
+    let source = ModelSource::new(&args.encoder, &args.revision).offline(args.offline);
+    let colbert_config = ColBertConfig::default().with_max_lengths(args.query_max_length, args.doc_max_length);
+    let encoder = ColBertEncoder::from_source(&source)?.with_colbert_config(colbert_config.clone())?;
+
+    let mut writer = ColBertIndexWriter::new(&args.index_dir, encoder.dimension() as u32)?;
+    writer.init_index(&args.index_type)?;
+    EncoderConfig::default().save(&args.index_dir)?;
+    colbert_config.save(&args.index_dir)?;
+
+    for batch in iterator.iter() {
+        let embeddings = encoder.encode_documents(&batch["text"], batch.get("title"))?;
+        writer.write(&batch["id"], &embeddings)?;
+    }
+    writer.save()?;
+    println!("{} documents encoded into {} vectors", writer.docids.len(), writer.doclens.iter().sum::<usize>());
+
+    let duration = start.elapsed();
+    println!("Time elapsed in expensive_function() is: {:?}", duration);
+
+    Ok(())
+}
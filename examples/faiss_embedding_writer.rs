@@ -1,8 +1,13 @@
 use rustserini::encode::auto::AutoDocumentEncoder;
-use rustserini::encode::base::{DocumentEncoder, RepresentationWriter};
+use rustserini::encode::cache::EmbeddingCache;
+use rustserini::encode::base::RepresentationWriter;
+use rustserini::encode::config::{EncoderConfig, FieldJoin, TruncationSide, TruncationStrategy};
+use rustserini::encode::pipeline::EncodingPipeline;
+use rustserini::encode::pooling::Pooling;
+use rustserini::encode::source::{parse_device, ModelSource};
 use rustserini::encode::vector_writer::{JsonlCollectionIterator, FaissRepresentationWriter};
-use std::collections::HashMap;
 use std::time::Instant;
+use candle_core::DType;
 use clap::{ArgAction, Parser};
 
 
@@ -52,15 +57,39 @@ struct Args {
     #[arg(long, default_value = "main")]
     revision: String,
 
+    /// Only use files from the local Hugging Face cache, never contacting the hub
+    #[arg(long, action=ArgAction::SetTrue)]
+    offline: bool,
+
+    /// Hugging Face cache root to use instead of the default one
+    #[arg(long)]
+    cache_dir: Option<String>,
+
     /// Tokenizer name or path
     #[arg(long)]
     tokenizer: String,
 
+    /// Tokenizer Revision
+    #[arg(long, default_value = "main")]
+    tokenizer_revision: String,
+
     /// Batch size for encoding
     #[arg(short, long, default_value_t = 4)]
     batch_size: usize,
 
-    /// GPU Device ==> cpu or cuda:0
+    /// Number of threads encoding batches in parallel
+    #[arg(long, default_value_t = 4)]
+    num_workers: usize,
+
+    /// Maximum number of padded tokens per batch, batch-size still bounds the number of documents
+    #[arg(long)]
+    max_tokens: Option<usize>,
+
+    /// Directory of the embedding cache, unchanged documents are read from it instead of being encoded again
+    #[arg(long)]
+    embedding_cache: Option<String>,
+
+    /// Device to run the model on: cpu, cuda or cuda:0, cuda requires the cuda feature
     #[arg(long, default_value = "cpu")]
     device: String,
 
@@ -68,17 +97,65 @@ struct Args {
     #[arg(long, action=ArgAction::SetTrue)]
     fp16: bool,
 
+    /// Explicit weights file to load, e.g. a quantized model-q8_0.gguf or an ONNX export onnx/model_quantized.onnx
+    #[arg(long)]
+    weights_file: Option<String>,
+
+    /// Pooling strategy: cls, mean, max, last_token or weighted_mean
+    #[arg(long, default_value = "cls")]
+    pooling: String,
+
+    /// Whether to L2-normalize the embeddings
+    #[arg(long, action=ArgAction::SetTrue)]
+    l2_norm: bool,
+
     /// max length of the input
     #[arg(short, long, default_value_t = 512)]
     max_length: u16,
 
+    /// Truncation strategy for title/text pairs: longest_first, only_first or only_second
+    #[arg(long, default_value = "longest_first")]
+    truncation_strategy: String,
+
+    /// Side to truncate from: left or right
+    #[arg(long, default_value = "right")]
+    truncation_side: String,
+
+    /// How titles and texts are combined: space, pair or separator:<string>
+    #[arg(long, default_value = "space")]
+    field_join: String,
+
+    /// Template for documents, with {title} and {text} placeholders, e.g. "passage: {text}"
+    #[arg(long)]
+    document_template: Option<String>,
+
+    /// Template for queries stored alongside the index, e.g. "query: {text}"
+    #[arg(long)]
+    query_template: Option<String>,
+
+    /// Maximum number of tokens kept from the title
+    #[arg(long)]
+    max_title_length: Option<usize>,
+
+    /// Maximum number of tokens kept from the text
+    #[arg(long)]
+    max_text_length: Option<usize>,
+
     /// Embedding dimension
     #[arg(long, default_value_t = 768)]
     embedding_dim: u32,
-}
 
-fn sanitize_string(s: &str) -> String {
-    s.replace("\"", "").replace("\\", "")
+    /// Keep only the first dimensions of the embeddings, for Matryoshka models
+    #[arg(long)]
+    truncate_dim: Option<usize>,
+
+    /// Faiss index_factory description, e.g. "PCA256,Flat" to learn a PCA reduction to 256 dimensions
+    #[arg(long, default_value = "Flat")]
+    index_type: String,
+
+    /// Number of embeddings used to train index types that need training
+    #[arg(long, default_value_t = 65536)]
+    training_sample: usize,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -88,37 +165,57 @@ fn main() -> anyhow::Result<()> {
     let fields: Vec<String> = args.fields.split(',').map(|s| s.to_string()).collect();
     let mut iterator: JsonlCollectionIterator =
         JsonlCollectionIterator::new(fields, "id".to_string(), args.delimiter, args.batch_size);
-    let _ = iterator.load(args.corpus);
+    iterator.load(args.corpus)?;
 
     println!("Initialize a representation writer and open a file to store the embeddings");
-    let mut writer = FaissRepresentationWriter::new(&args.embeddings_dir, args.embedding_dim);
-    let _ = writer.open_file();
-
-    let encoder = AutoDocumentEncoder::new(
-        &args.encoder,
-        &args.revision,
-    );
-
-    let mut counter: usize = 0;
-    for batch in iterator.iter() {
-        let mut batch_info = HashMap::new();
-
-        let batch_text: Vec<String> = batch["text"].iter().map(|x| sanitize_string(x)).collect();
-        let batch_id: Vec<String> = batch["id"].iter().map(|x| sanitize_string(x)).collect();
-
-        let embeddings = &encoder.encode(&batch_text, None, "cls")?;
-
-        let mut embeddings: Vec<f32> = embeddings.flatten_all()?.to_vec1::<f32>()?;
-
-        batch_info.insert("text", batch_text);
-        batch_info.insert("id", batch_id);
-
-        let _ = &writer.write(&batch_info, &mut embeddings);
-
-        counter += 1;
-        println!("Batch {} encoded", counter);
-
-        break;
+    let dimension = args.truncate_dim.map(|dim| dim as u32).unwrap_or(args.embedding_dim);
+    let mut writer =
+        FaissRepresentationWriter::new(&args.embeddings_dir, dimension)?.with_training_sample(args.training_sample);
+    writer.init_index(dimension, &args.index_type)?;
+    writer.open_file()?;
+
+    let pooling: Pooling = args.pooling.parse()?;
+    let mut source = ModelSource::new(&args.encoder, &args.revision).offline(args.offline);
+    let mut tokenizer_source = ModelSource::new(&args.tokenizer, &args.tokenizer_revision).offline(args.offline);
+    if let Some(cache_dir) = &args.cache_dir {
+        source = source.with_cache_dir(cache_dir);
+        tokenizer_source = tokenizer_source.with_cache_dir(cache_dir);
+    }
+    if args.fp16 {
+        source = source.with_dtype(DType::F16);
+    }
+    source = source.with_device(parse_device(&args.device)?);
+    if let Some(weights_file) = &args.weights_file {
+        source = source.with_weights_file(weights_file);
+    }
+    let truncation_strategy: TruncationStrategy = args.truncation_strategy.parse()?;
+    let truncation_side: TruncationSide = args.truncation_side.parse()?;
+    let mut config = EncoderConfig::new(args.max_length as usize)
+        .with_truncation(truncation_strategy, truncation_side)
+        .with_field_join(args.field_join.parse::<FieldJoin>()?);
+    config.max_title_length = args.max_title_length;
+    config.max_text_length = args.max_text_length;
+    config.document_template = args.document_template.clone();
+    config.query_template = args.query_template.clone();
+    config.truncate_dim = args.truncate_dim;
+    config.save(&args.embeddings_dir)?;
+    let cache = match &args.embedding_cache {
+        Some(cache_dir) => Some(EmbeddingCache::open(cache_dir, &source, &tokenizer_source, pooling, args.l2_norm, &config)?),
+        None => None,
+    };
+    let encoder = AutoDocumentEncoder::from_sources(&source, &tokenizer_source)?.with_config(config)?;
+
+    let mut pipeline = EncodingPipeline::new(args.batch_size, args.num_workers).with_pooling(pooling, args.l2_norm);
+    if let Some(max_tokens) = args.max_tokens {
+        pipeline = pipeline.with_max_tokens(max_tokens);
+    }
+    if let Some(cache) = cache {
+        pipeline = pipeline.with_cache(cache);
+    }
+    let encoded = pipeline.run(&mut iterator, &encoder, &mut writer)?;
+    println!("{} documents encoded", encoded);
+    if let Some(cache) = pipeline.cache() {
+        println!("{} documents read from the embedding cache, {} encoded", cache.hits(), cache.misses());
     }
 
     writer.save_index()?;
@@ -0,0 +1,90 @@
+use rustserini::encode::base::SparseDocumentEncoder;
+use rustserini::encode::config::EncoderConfig;
+use rustserini::encode::source::ModelSource;
+use rustserini::encode::splade::SpladeDocumentEncoder;
+use rustserini::encode::unicoil::UniCoilDocumentEncoder;
+use rustserini::encode::vector_writer::{JsonVectorCollectionWriter, JsonlCollectionIterator};
+use std::time::Instant;
+use clap::{ArgAction, Parser};
+
+
+/// Simple program to encode a corpus into an Anserini JsonVectorCollection of quantized impact weights
+/// cargo run --example impact_vector_writer -- --corpus corpus/msmarco-passage/corpus.jsonl --embeddings-dir corpus/msmarco-passage-unicoil --encoder castorini/unicoil-msmarco-passage
+/// The collection is then indexed with:
+/// cargo run --bin lucene_indexer -- --collection JsonVectorCollection --input corpus/msmarco-passage-unicoil --index indexes/msmarco-passage-unicoil --generator DefaultLuceneDocumentGenerator --impact --pretokenized
+
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Directory that contains corpus files to be encoded, in jsonl format.
+    #[arg(short, long)]
+    corpus: String,
+
+    /// Fields that contents in jsonl has (in order) separated by comma.
+    #[arg(short, long, default_value = "text")]
+    fields: String,
+
+    /// directory to store encoded corpus
+    #[arg(short, long, required = true)]
+    embeddings_dir: String,
+
+    /// Encoder name or path
+    #[arg(long, default_value = "castorini/unicoil-msmarco-passage")]
+    encoder: String,
+
+    /// Encoder Revision
+    #[arg(long, default_value = "main")]
+    revision: String,
+
+    /// Encoder type: unicoil or splade
+    #[arg(long, default_value = "unicoil")]
+    encoder_type: String,
+
+    /// Only use files from the local Hugging Face cache, never contacting the hub
+    #[arg(long, action=ArgAction::SetTrue)]
+    offline: bool,
+
+    /// Batch size for encoding
+    #[arg(short, long, default_value_t = 32)]
+    batch_size: usize,
+
+    /// max length of the input
+    #[arg(short, long, default_value_t = 512)]
+    max_length: usize,
+
+    /// Factor the weights are multiplied by before rounding them to integers
+    #[arg(long, default_value_t = 100.0)]
+    quantization_factor: f32,
+}
+
+fn main() -> anyhow::Result<()> {
+    let start = Instant::now();
+    let args = Args::parse();
+
+    let fields: Vec<String> = args.fields.split(',').map(|s| s.to_string()).collect();
+    let mut iterator: JsonlCollectionIterator =
+        JsonlCollectionIterator::new(fields, "id".to_string(), "\n".to_string(), args.batch_size);
+    iterator.load(args.corpus)?;
+
+    let source = ModelSource::new(&args.encoder, &args.revision).offline(args.offline);
+    let config = EncoderConfig::new(args.max_length);
+    let encoder: Box<dyn SparseDocumentEncoder> = match args.encoder_type.as_str() {
+        "unicoil" => Box::new(UniCoilDocumentEncoder::from_source(&source)?.with_config(config)?),
+        "splade" => Box::new(SpladeDocumentEncoder::from_source(&source)?.with_config(config)?),
+        encoder_type => anyhow::bail!("Unknown encoder type {}, expected unicoil or splade", encoder_type),
+    };
+
+    let mut writer = JsonVectorCollectionWriter::new(&args.embeddings_dir).with_quantization_factor(args.quantization_factor);
+    writer.open_file()?;
+
+    for batch in iterator.iter() {
+        let weights = encoder.encode(&batch["text"], batch.get("title"))?;
+        writer.write(&batch, &weights)?;
+    }
+
+    let duration = start.elapsed();
+    println!("Time elapsed in expensive_function() is: {:?}", duration);
+
+    Ok(())
+}
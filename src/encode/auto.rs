@@ -1,71 +1,275 @@
-use std::vec;
-
 use crate::encode::base::DocumentEncoder;
+use crate::encode::config::EncoderConfig;
+use crate::encode::core::EncoderCore;
+use crate::encode::onnx::OnnxModel;
+use crate::encode::pooling::Pooling;
+use crate::encode::quantized_bert::QuantizedBertModel;
+use crate::encode::registry::ModelRegistry;
+use crate::encode::source::{ModelSource, WeightFiles};
+
+use crate::error::{Error, Result};
 
-use anyhow::{anyhow, Error as E, Result};
-use hf_hub::{api::sync::Api, Cache, Repo, RepoType};
 use candle_core::{DType, Device, Tensor};
 use candle_nn::VarBuilder;
-use candle_transformers::models::bert::{BertModel,BertForMaskedLM, Config};
-use tokenizers::{PaddingParams, Tokenizer};
+use candle_transformers::models::bert::{BertModel, BertForMaskedLM, Config as BertConfig};
+use candle_transformers::models::distilbert::{DistilBertForMaskedLM, DistilBertModel};
+use candle_transformers::models::jina_bert::BertModel as JinaBertModel;
+use candle_transformers::models::modernbert::ModernBert;
+use candle_transformers::models::t5::T5EncoderModel;
+use candle_transformers::models::xlm_roberta::XLMRobertaModel;
+use candle_nn::Module;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use tokenizers::{Encoding, PaddingParams, Tokenizer};
 use serde_json::Value;
 
 pub const FLOATING_DTYPE: DType = DType::F32;
 pub const LONG_DTYPE: DType = DType::I64;
 
+/// A backbone that is not built into rustserini, plugged in through the ModelRegistry
+pub trait EncoderModel: Send + Sync {
+    // Run the model and return (batch, seq_len, hidden_size) hidden states
+    fn forward(&self, token_ids: &Tensor, token_type_ids: &Tensor, attention_mask: &Tensor) -> Result<Tensor>;
+}
+
 pub enum Model {
     BertModel {model: BertModel},
     BertForMaskedLM {model: BertForMaskedLM},
+    DistilBertModel {model: DistilBertModel},
+    DistilBertForMaskedLM {model: DistilBertForMaskedLM},
+    XLMRobertaModel {model: XLMRobertaModel},
+    ModernBertModel {model: ModernBert},
+    JinaBertModel {model: JinaBertModel},
+    // T5EncoderModel::forward takes &mut self
+    T5EncoderModel {model: Mutex<T5EncoderModel>},
+    QuantizedBertModel {model: QuantizedBertModel},
+    OnnxModel {model: OnnxModel},
+    Custom {model: Box<dyn EncoderModel>},
 }
 
+/// Which output of a checkpoint to load: encoder hidden states or masked language modelling logits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum OutputModelType{
     BertModel,
     BertForMaskedLM,
 }
 
+impl Model {
+    pub fn forward(&self, token_ids: &Tensor, token_type_ids: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+        /*
+        Run the model over a padded batch, returning hidden states for encoders and vocabulary logits for MLM heads
+        */
+        let output = match self {
+            Model::BertModel {model} => model.forward(token_ids, token_type_ids, Some(attention_mask))?,
+            Model::BertForMaskedLM {model} => model.forward(token_ids, token_type_ids, Some(attention_mask))?,
+            Model::DistilBertModel {model} => model.forward(token_ids, &distilbert_mask(attention_mask)?)?,
+            Model::DistilBertForMaskedLM {model} => model.forward(token_ids, &distilbert_mask(attention_mask)?)?,
+            Model::XLMRobertaModel {model} => {
+                model.forward(token_ids, attention_mask, token_type_ids, None, None, None)?
+            },
+            Model::ModernBertModel {model} => model.forward(token_ids, attention_mask)?,
+            Model::JinaBertModel {model} => {
+                forward_unpadded(token_ids, attention_mask, |token_ids| Ok(model.forward(token_ids)?))?
+            },
+            Model::T5EncoderModel {model} => {
+                let mut model = model
+                    .lock()
+                    .map_err(|_| candle_core::Error::msg("T5 encoder lock was poisoned"))?;
+                forward_unpadded(token_ids, attention_mask, |token_ids| Ok(model.forward(token_ids)?))?
+            },
+            Model::QuantizedBertModel {model} => model.forward(token_ids, token_type_ids, attention_mask)?,
+            Model::OnnxModel {model} => model.forward(token_ids, token_type_ids, attention_mask)?,
+            Model::Custom {model} => model.forward(token_ids, token_type_ids, attention_mask)?,
+        };
+
+        Ok(output)
+    }
+
+    pub fn is_masked_lm(&self) -> bool {
+        match self {
+            Model::OnnxModel {model} => model.is_masked_lm(),
+            model => matches!(model, Model::BertForMaskedLM {..} | Model::DistilBertForMaskedLM {..}),
+        }
+    }
+}
+
+pub fn batch_tensors(encodings: &[Encoding], device: &Device) -> Result<(Tensor, Tensor, Tensor)> {
+    /*
+    Stack a padded batch of encodings into token ids, token type ids and attention mask tensors
+    */
+    let to_tensor = |values: &[u32]| Tensor::new(values, device);
+
+    let token_ids = encodings
+        .iter()
+        .map(|encoding| to_tensor(encoding.get_ids()))
+        .collect::<candle_core::Result<Vec<_>>>()?;
+    let token_type_ids = encodings
+        .iter()
+        .map(|encoding| to_tensor(encoding.get_type_ids()))
+        .collect::<candle_core::Result<Vec<_>>>()?;
+    let attention_mask = encodings
+        .iter()
+        .map(|encoding| to_tensor(encoding.get_attention_mask()))
+        .collect::<candle_core::Result<Vec<_>>>()?;
+
+    Ok((
+        Tensor::stack(&token_ids, 0)?,
+        Tensor::stack(&token_type_ids, 0)?,
+        Tensor::stack(&attention_mask, 0)?,
+    ))
+}
+
+fn distilbert_mask(attention_mask: &Tensor) -> Result<Tensor> {
+    /*
+    DistilBERT masks out the positions that are set, the inverse of the tokenizer's attention mask
+    */
+    Ok(attention_mask.eq(0u32)?.unsqueeze(1)?.unsqueeze(1)?)
+}
+
+fn forward_unpadded(
+    token_ids: &Tensor,
+    attention_mask: &Tensor,
+    mut forward: impl FnMut(&Tensor) -> Result<Tensor>,
+) -> Result<Tensor> {
+    /*
+    Run models that take no attention mask one sequence at a time without its padding,
+    then pad the hidden states back with zeros so batched and unbatched outputs match
+    */
+    let (_n_sentence, n_tokens) = token_ids.dims2()?;
+    let attention_mask = attention_mask.to_dtype(DType::U32)?.to_vec2::<u32>()?;
+
+    let hidden_states = attention_mask
+        .iter()
+        .enumerate()
+        .map(|(i, mask)| {
+            let start = mask.iter().position(|&m| m == 1).unwrap_or(0);
+            let length = mask.iter().filter(|&&m| m == 1).count();
+            let sequence = token_ids.get(i)?.narrow(0, start, length)?.unsqueeze(0)?;
+            let hidden_state = forward(&sequence)?.squeeze(0)?;
+            Ok(hidden_state.pad_with_zeros(0, start, n_tokens - start - length)?)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Tensor::stack(&hidden_states, 0)?)
+}
+
 /// An AutoDocumentEncoder for encoding documents with BERT-style  encoding models
 pub struct AutoDocumentEncoder {
-    model: Model,
-    tokenizer: Tokenizer,
-    device: Device,
-}
-
-
-pub fn build_model_and_tokenizer(model_name_or_path: impl Into<String>, offline: bool, revision: &str) -> Result<(Model, Tokenizer)> {
-    let device = Device::Cpu;
-    let (model_id, revision) = (model_name_or_path.into(), revision.into());
-    let repo = Repo::with_revision(model_id, RepoType::Model, revision);
-
-    let (config_filename, tokenizer_filename, weights_filename) = if offline {
-        let cache = Cache::default().repo(repo);
-        (
-            cache
-                .get("config.json")
-                .ok_or(anyhow!("Missing config file in cache"))?,
-            cache
-                .get("tokenizer.json")
-                .ok_or(anyhow!("Missing tokenizer file in cache"))?,
-            cache
-                .get("model.safetensors")
-                .ok_or(anyhow!("Missing weights file in cache"))?,
-        )
-    } else {
-        let api = Api::new()?;
-        let api = api.repo(repo);
-        (
-            api.get("config.json")?,
-            api.get("tokenizer.json")?,
-            api.get("model.safetensors")?,
-        )
+    core: EncoderCore,
+}
+
+
+/// Wrapper prefixes in front of the backbone weights, e.g. in DPR or *ForMaskedLM exports
+pub const WEIGHT_PREFIXES: [&str; 8] = [
+    "ctx_encoder.bert_model.",
+    "question_encoder.bert_model.",
+    "bert_model.",
+    "bert.",
+    "roberta.",
+    "distilbert.",
+    "encoder.",
+    "model.",
+];
+
+pub fn load_var_builder(weights: &WeightFiles, dtype: DType, device: &Device) -> Result<VarBuilder<'static>> {
+    /*
+    Build a VarBuilder over safetensors or PyTorch weights, single or sharded, resolving prefixed tensor names
+    */
+    let (vb, tensor_names) = match weights {
+        WeightFiles::SafeTensors(filenames) => {
+            let tensors = unsafe { candle_core::safetensors::MmapedSafetensors::multi(filenames)? };
+            let tensor_names: Vec<String> = tensors.tensors().into_iter().map(|(name, _)| name).collect();
+            let vb = unsafe { VarBuilder::from_mmaped_safetensors(filenames, dtype, device)? };
+            (vb, tensor_names)
+        }
+        WeightFiles::PyTorch(filenames) if filenames.len() == 1 => {
+            let tensor_names = candle_core::pickle::read_pth_tensor_info(&filenames[0], false, None)?
+                .into_iter()
+                .map(|info| info.name)
+                .collect();
+            let vb = VarBuilder::from_pth(&filenames[0], dtype, device)?;
+            (vb, tensor_names)
+        }
+        WeightFiles::PyTorch(filenames) => {
+            let mut tensors = HashMap::new();
+            for filename in filenames {
+                tensors.extend(candle_core::pickle::read_all(filename)?);
+            }
+            let tensor_names = tensors.keys().cloned().collect();
+            let vb = VarBuilder::from_tensors(tensors, dtype, device);
+            (vb, tensor_names)
+        }
+        WeightFiles::Gguf(filename) => {
+            return Err(Error::ModelLoad(format!(
+                "{} holds quantized weights, they are loaded as a QuantizedBertModel rather than through a VarBuilder",
+                filename.display()
+            )));
+        }
+        WeightFiles::Onnx(filename) => {
+            return Err(Error::ModelLoad(format!(
+                "{} is an ONNX graph, it is run as an OnnxModel rather than through a VarBuilder",
+                filename.display()
+            )));
+        }
     };
 
-    println!("config_filename: {}", config_filename.display());
-    println!("tokenizer_filename: {}", tokenizer_filename.display());
-    println!("weights_filename: {}", weights_filename.display());
+    Ok(with_prefix_fallback(vb, tensor_names))
+}
+
+fn with_prefix_fallback(vb: VarBuilder<'static>, tensor_names: Vec<String>) -> VarBuilder<'static> {
+    /*
+    Resolve tensors that are not found under their own name through the wrapper prefix of the checkpoint,
+    so that e.g. `embeddings.word_embeddings.weight` is read from `ctx_encoder.bert_model.embeddings.word_embeddings.weight`
+    */
+    let prefix = WEIGHT_PREFIXES.iter().find(|prefix| {
+        tensor_names
+            .iter()
+            .any(|name| name.starts_with(*prefix) && name.contains("embeddings."))
+    });
 
+    match prefix {
+        Some(prefix) => {
+            let prefix = prefix.to_string();
+            let tensor_names: HashSet<String> = tensor_names.into_iter().collect();
+            vb.rename_f(move |name: &str| {
+                let prefixed = format!("{}{}", prefix, name);
+                if !tensor_names.contains(name) && tensor_names.contains(&prefixed) {
+                    prefixed
+                } else {
+                    name.to_string()
+                }
+            })
+        }
+        None => vb,
+    }
+}
 
-    let config = std::fs::read_to_string(config_filename)?;
-    let mut tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
+pub fn build_model_and_tokenizer(
+    source: &ModelSource,
+    output_model_type: OutputModelType,
+) -> Result<(Model, Tokenizer)> {
+    build_model_and_tokenizer_with_registry(source, output_model_type, &ModelRegistry::default())
+}
+
+pub fn build_model_and_tokenizer_with_registry(
+    source: &ModelSource,
+    output_model_type: OutputModelType,
+    registry: &ModelRegistry,
+) -> Result<(Model, Tokenizer)> {
+    let model = build_model(source, output_model_type, registry)?;
+    let tokenizer = load_tokenizer(source)?;
+
+    Ok((model, tokenizer))
+}
+
+pub fn load_tokenizer(source: &ModelSource) -> Result<Tokenizer> {
+    /*
+    Load tokenizer.json from a source and pad batches to their longest sequence
+    */
+    let tokenizer_filename = source.get("tokenizer.json")?;
+    println!("tokenizer_filename: {}", tokenizer_filename.display());
+
+    let mut tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(|err| Error::Tokenizer(err.to_string()))?;
 
     if let Some(pp) = tokenizer.get_padding_mut() {
         pp.strategy = tokenizers::PaddingStrategy::BatchLongest
@@ -77,48 +281,124 @@ pub fn build_model_and_tokenizer(model_name_or_path: impl Into<String>, offline:
         tokenizer.with_padding(Some(pp));
     }
 
-    let vb =
-        unsafe { VarBuilder::from_mmaped_safetensors(&[weights_filename], FLOATING_DTYPE, &device)? };
-    
+    Ok(tokenizer)
+}
+
+pub fn build_model(
+    source: &ModelSource,
+    output_model_type: OutputModelType,
+    registry: &ModelRegistry,
+) -> Result<Model> {
+    /*
+    Load config.json and the weights from a source and build the matching model from the registry
+    ONNX graphs carry their own architecture, so an export only needs its model.onnx next to tokenizer.json
+    */
+    let device = source.device.clone();
+
+    let weights = source.weights()?;
+    println!("weights: {:?}", weights);
+
+    if let WeightFiles::Onnx(filename) = &weights {
+        return Ok(Model::OnnxModel { model: OnnxModel::load(filename, output_model_type)? });
+    }
+
+    let config_filename = source.get("config.json")?;
+    println!("config_filename: {}", config_filename.display());
+
+    let config = std::fs::read_to_string(config_filename)?;
+
     let model_configuration: Value = serde_json::from_str(&config)?;
     let model_architecture = &model_configuration["architectures"][0].as_str();
 
     println!("model_architecture: {:?}", model_architecture);
 
-    let model = match model_architecture {
-        Some("BertModel") => {
-            let config: Config = serde_json::from_str(&config)?;
-            let model = BertModel::load(vb, &config)?;
-            Model::BertModel {model}
-        }
-        Some("BertForMaskedLM") => {
-            let config: Config = serde_json::from_str(&config)?;
-            let model = BertModel::load(vb, &config)?;
-            Model::BertModel {model}
+    if let WeightFiles::Gguf(filename) = &weights {
+        return load_quantized_bert(filename, &config, output_model_type, &device);
+    }
+
+    match source.dtype {
+        // The CPU has no bf16 matrix multiplication, bf16 checkpoints are upcast to f32 exactly
+        DType::F32 | DType::BF16 => {
+            let vb = load_var_builder(&weights, DType::F32, &device)?;
+            registry.load(vb, &config, output_model_type)
         }
-        _ => panic!("Invalid model_type")
-    };
+        DType::F16 => load_half_bert(&weights, &config, output_model_type, &device),
+        dtype => Err(Error::ModelLoad(format!(
+            "{:?} weights are not supported on the CPU, load them in f16, bf16 or f32",
+            dtype
+        ))),
+    }
+}
 
-    Ok((model, tokenizer))
+fn check_quantized_bert(config: &str, output_model_type: OutputModelType, format: &str) -> Result<BertConfig> {
+    /*
+    Only the hidden states of BERT encoders run on quantized or f16 weights so far
+    */
+    if output_model_type == OutputModelType::BertForMaskedLM {
+        return Err(Error::ModelLoad(format!(
+            "{} weights only support encoder hidden states, not masked language modelling heads",
+            format
+        )));
+    }
+    let model_configuration: Value = serde_json::from_str(config)?;
+    let model_type = model_configuration["model_type"].as_str().unwrap_or("bert");
+    if model_type != "bert" {
+        return Err(Error::ModelLoad(format!(
+            "{} weights are only supported for BERT models, found model_type {}",
+            format, model_type
+        )));
+    }
+
+    Ok(serde_json::from_str(config)?)
 }
 
-pub fn normalize_l2(v: &Tensor) -> Result<Tensor> {
-    Ok(v.broadcast_div(&v.sqr()?.sum_keepdim(1)?.sqrt()?)?)
+fn load_half_bert(
+    weights: &WeightFiles,
+    config: &str,
+    output_model_type: OutputModelType,
+    device: &Device,
+) -> Result<Model> {
+    /*
+    Build a BERT encoder whose linear layers run in f16, candle's own BertModel overflows its
+    attention mask in f16 and the CPU has no bf16 matrix multiplication
+    */
+    let config = check_quantized_bert(config, output_model_type, "F16")?;
+    let vb = load_var_builder(weights, DType::F32, device)?;
+    let model = QuantizedBertModel::load_f16(vb, &config)?;
+
+    Ok(Model::QuantizedBertModel { model })
 }
 
-pub fn mean_pooling(last_hidden_state: Tensor, normalize_embeddings: bool) -> Result<Tensor, E> {
+fn load_quantized_bert(
+    filename: &std::path::Path,
+    config: &str,
+    output_model_type: OutputModelType,
+    device: &Device,
+) -> Result<Model> {
     /*
-    Compute mean pooling of BERT hidden states
+    Build a BERT encoder over GGUF quantized weights, the only quantized architecture supported so far
     */
-    let (_n_sentence, n_tokens, _hidden_size) = last_hidden_state.dims3()?;
-    let embeddings = (last_hidden_state.sum(1)? / (n_tokens as f64))?;
-    let embeddings = if normalize_embeddings {
-        normalize_l2(&embeddings)?
-    } else {
-        embeddings
-    };
+    let config = check_quantized_bert(config, output_model_type, "Quantized GGUF")?;
+    let vb = candle_transformers::quantized_var_builder::VarBuilder::from_gguf(filename, device)?;
+    let model = QuantizedBertModel::load(vb, &config)?;
 
-    Ok(embeddings)
+    Ok(Model::QuantizedBertModel { model })
+}
+
+impl AutoDocumentEncoder {
+    pub fn from_source(source: &ModelSource) -> Result<Self> {
+        Self::from_sources(source, source)
+    }
+
+    pub fn from_sources(model_source: &ModelSource, tokenizer_source: &ModelSource) -> Result<Self> {
+        let core = EncoderCore::from_sources(model_source, tokenizer_source, OutputModelType::BertModel)?;
+        Ok(Self { core })
+    }
+
+    pub fn with_config(mut self, config: EncoderConfig) -> Result<Self> {
+        self.core = self.core.with_config(config)?;
+        Ok(self)
+    }
 }
 
 impl DocumentEncoder for AutoDocumentEncoder {
@@ -126,83 +406,26 @@ impl DocumentEncoder for AutoDocumentEncoder {
     fn new(
         model_name: &str,
         revision: &str,
-    ) -> AutoDocumentEncoder {
-        let device = Device::Cpu;
-        let (model, tokenizer) = build_model_and_tokenizer(model_name, false, revision).unwrap();
-        Self { model, tokenizer, device }
+    ) -> Result<AutoDocumentEncoder> {
+        Self::from_source(&ModelSource::new(model_name, revision))
     }
 
     fn encode(
         &self,
         texts: &Vec<String>,
         titles: Option<&Vec<String>>,
-        pooler_type: &str,
-    ) -> Result<Tensor, E> {
+        pooling: Pooling,
+        normalize: bool,
+    ) -> Result<Tensor> {
         /*
         Encode a list of texts and/or titles into a list of vectors
         */
-        let texts = if let Some(titles) = titles  {
-            texts
-                .iter()
-                .zip(titles.iter())
-                .map(|(text, title)| format!("{} {}", title, text))
-                .collect::<Vec<_>>()
-        } else {
-            texts.to_owned()
-        };
-
-        let tokens = self.tokenizer
-            .encode_batch(texts, true, )
-            .map_err(E::msg)?;
-
-        let token_ids = tokens
-            .iter()
-            .map(|tokens| {
-                let tokens = tokens.get_ids().to_vec();
-                Ok(Tensor::new(tokens.as_slice(), &self.device)?)
-            })
-            .collect::<Result<Vec<_>>>()?;
-        let attention_mask = tokens
-            .iter()
-            .map(|tokens| {
-                let tokens = tokens.get_attention_mask().to_vec();
-                Ok(Tensor::new(tokens.as_slice(), &self.device)?)
-            })
-            .collect::<Result<Vec<_>>>()?;
-            
-        let token_ids = Tensor::stack(&token_ids, 0)?;
-        let token_type_ids = token_ids.zeros_like()?;
-        let attention_mask = Tensor::stack(&attention_mask, 0)?;
-
-        let hidden_state: Tensor = match &self.model {
-            Model::BertModel {model} => {
-                let hidden_state: Tensor = model.forward(&token_ids, &token_type_ids, Some(&attention_mask))?;
-                hidden_state
-            },
-            Model::BertForMaskedLM {model} => {
-                let hidden_state: Tensor = model.forward(&token_ids, &token_type_ids, Some(&attention_mask))?;
-                hidden_state
-            },            
-        };
-
-        let embeddings: Tensor = if pooler_type == "mean" {
-            mean_pooling(hidden_state, false)?
-            
-        } else if pooler_type == "cls" {
-            
-            let (_n_sentence, _n_tokens, _hidden_size) = hidden_state.dims3()?;
-
-            let mut out_embeding = vec![];
-            for i in 0.._n_sentence{
+        let tokens = self.core.tokenize_documents(texts, titles)?;
 
-                let embeding = hidden_state.get(i)?.get(0)?;
-                out_embeding.push(embeding);
-            }
-            Tensor::stack(&out_embeding, 0)?
-        } else {
-            panic!("pooler_type must be either mean or cls");
-        };
+        self.core.embed(&tokens, pooling, normalize)
+    }
 
-        Ok(embeddings)
+    fn token_lengths(&self, texts: &[String], titles: Option<&Vec<String>>) -> Result<Vec<usize>> {
+        self.core.token_lengths(texts, titles)
     }
 }
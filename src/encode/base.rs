@@ -1,5 +1,7 @@
 extern crate serde_json;
-use anyhow::{Error, Result};
+use crate::encode::pooling::Pooling;
+use crate::error::Result;
+
 use candle_core::Tensor;
 use std::collections::HashMap;
 
@@ -9,15 +11,68 @@ pub trait DocumentEncoder {
     fn new(
         model_name: &str,
         revision: &str,
-    ) -> Self;
+    ) -> Result<Self>
+    where
+        Self: Sized;
 
     // Encode a document or a set of documents into a vector of floats
     fn encode(
         &self,
         texts: &Vec<String>,
         titles: Option<&Vec<String>>,
-        pooler_type: &str,
-    ) -> Result<Tensor, Error>;
+        pooling: Pooling,
+        normalize: bool,
+    ) -> Result<Tensor>;
+
+    // Number of tokens of each document, used to batch documents of similar lengths together
+    // Defaults to a whitespace word count for encoders that do not expose their tokenizer
+    fn token_lengths(&self, texts: &[String], titles: Option<&Vec<String>>) -> Result<Vec<usize>> {
+        let lengths = texts
+            .iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let title = titles.map(|titles| titles[i].split_whitespace().count()).unwrap_or(0);
+                title + text.split_whitespace().count()
+            })
+            .collect();
+        Ok(lengths)
+    }
+}
+
+/// A base trait for sparse document encoders producing term -> weight maps
+pub trait SparseDocumentEncoder {
+    // instantiating a new SparseDocumentEncoder instance
+    fn new(
+        model_name: &str,
+        revision: &str,
+    ) -> Result<Self>
+    where
+        Self: Sized;
+
+    // Encode a document or a set of documents into term -> weight maps
+    fn encode(
+        &self,
+        texts: &Vec<String>,
+        titles: Option<&Vec<String>>,
+    ) -> Result<Vec<HashMap<String, f32>>>;
+}
+
+/// A base trait for multi-vector document encoders keeping one vector per token
+pub trait MultiVectorDocumentEncoder {
+    // instantiating a new MultiVectorDocumentEncoder instance
+    fn new(
+        model_name: &str,
+        revision: &str,
+    ) -> Result<Self>
+    where
+        Self: Sized;
+
+    // Encode a document or a set of documents into one (tokens, dimension) tensor each
+    fn encode(
+        &self,
+        texts: &Vec<String>,
+        titles: Option<&Vec<String>>,
+    ) -> Result<Vec<Tensor>>;
 }
 
 pub trait RepresentationWriter {
@@ -26,20 +81,22 @@ pub trait RepresentationWriter {
         &mut self,
         batch_info: &HashMap<&str, Vec<String>>,
         embedding: &mut Vec<f32>,
-    ) -> Result<(), anyhow::Error>;
+    ) -> Result<()>;
 
     // Create a new instance of a RepresentationWriter
-    fn new(path: &str, dimension: u32) -> Self;
+    fn new(path: &str, dimension: u32) -> Result<Self>
+    where
+        Self: Sized;
 
     // Open File
-    fn open_file(&mut self) -> Result<(), anyhow::Error>;
+    fn open_file(&mut self) -> Result<()>;
 
     // Save Index to file
-    fn save_index(&mut self) -> Result<(), anyhow::Error>;
+    fn save_index(&mut self) -> Result<()>;
 
     // Initialize Index
-    fn init_index(&mut self, dim: u32, index_type: &str);
+    fn init_index(&mut self, dim: u32, index_type: &str) -> Result<()>;
 
     // Save Docids to file
-    fn save_docids(&mut self) -> Result<(), anyhow::Error>;
+    fn save_docids(&mut self) -> Result<()>;
 }
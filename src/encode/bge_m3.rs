@@ -0,0 +1,229 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::encode::auto::{load_var_builder, OutputModelType};
+use crate::encode::base::{DocumentEncoder, MultiVectorDocumentEncoder, SparseDocumentEncoder};
+use crate::encode::config::EncoderConfig;
+use crate::encode::core::EncoderCore;
+use crate::encode::pooling::{cls_pooling, normalize_l2, Pooling};
+use crate::encode::source::{ModelSource, WeightFiles};
+use crate::error::{Error, Result};
+use crate::searcher::faiss::model::{QueryEncoder, QueryType};
+
+use candle_core::{DType, IndexOp, Module, Tensor, D};
+use candle_nn::Linear;
+use tokenizers::Encoding;
+
+/// The three representations of a batch computed from a single forward pass
+/// `dense` is the (batch, hidden_size) normalized [CLS] embedding, `sparse` holds one token -> weight map per text
+/// and `multi_vector` one normalized (tokens, dimension) tensor per text
+pub struct M3Embeddings {
+    pub dense: Tensor,
+    pub sparse: Vec<HashMap<String, f32>>,
+    pub multi_vector: Vec<Tensor>,
+}
+
+/// A BgeM3Encoder for encoding texts into dense, lexical sparse and ColBERT multi-vector representations at once
+/// The XLM-RoBERTa backbone runs once, `sparse_linear.pt` turns its hidden states into token weights and
+/// `colbert_linear.pt` into per-token vectors, as in BAAI/bge-m3
+/// It is designed to be a parallel of this Python Class
+/// https://github.com/FlagOpen/FlagEmbedding/blob/master/FlagEmbedding/inference/embedder/encoder_only/m3.py
+pub struct BgeM3Encoder {
+    core: EncoderCore,
+    sparse_linear: Linear,
+    colbert_linear: Linear,
+    special_token_ids: HashSet<u32>,
+    hidden_size: usize,
+}
+
+fn load_head(source: &ModelSource, filename: &str, core: &EncoderCore) -> Result<Linear> {
+    /*
+    Load a linear head stored as a PyTorch state dict next to the backbone
+    */
+    let vb = load_var_builder(&WeightFiles::PyTorch(vec![source.get(filename)?]), DType::F32, core.device())?;
+    let missing = |_| Error::ModelLoad(format!("{} does not hold a linear layer", filename));
+    let weight = vb.get_unchecked("weight").map_err(missing)?;
+    let bias = vb.get_unchecked("bias").map_err(missing)?;
+    Ok(Linear::new(weight, Some(bias)))
+}
+
+impl BgeM3Encoder {
+    pub fn from_source(source: &ModelSource) -> Result<Self> {
+        Self::from_sources(source, source)
+    }
+
+    pub fn from_sources(model_source: &ModelSource, tokenizer_source: &ModelSource) -> Result<Self> {
+        /*
+        sparse_linear.pt and colbert_linear.pt are read from the model source, next to the backbone
+        */
+        let core = EncoderCore::from_sources(model_source, tokenizer_source, OutputModelType::BertModel)?;
+        let sparse_linear = load_head(model_source, "sparse_linear.pt", &core)?;
+        let colbert_linear = load_head(model_source, "colbert_linear.pt", &core)?;
+
+        // The dense embedding is the [CLS] hidden state, as wide as the backbone
+        let config: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(model_source.get("config.json")?)?)?;
+        let hidden_size = config["hidden_size"]
+            .as_u64()
+            .ok_or_else(|| Error::ModelLoad("config.json does not provide a hidden_size".to_string()))?
+            as usize;
+
+        // Special tokens never receive a lexical weight
+        let special_token_ids = ["<s>", "</s>", "<pad>", "<unk>", "[CLS]", "[SEP]", "[PAD]", "[UNK]"]
+            .iter()
+            .filter_map(|token| core.tokenizer().token_to_id(token))
+            .collect();
+
+        Ok(Self { core, sparse_linear, colbert_linear, special_token_ids, hidden_size })
+    }
+
+    pub fn with_config(mut self, config: EncoderConfig) -> Result<Self> {
+        self.core = self.core.with_config(config)?;
+        Ok(self)
+    }
+
+    pub fn dense_dimension(&self) -> Result<usize> {
+        Ok(self.hidden_size)
+    }
+
+    pub fn multi_vector_dimension(&self) -> Result<usize> {
+        Ok(self.colbert_linear.weight().dim(0)?)
+    }
+
+    pub fn encode_documents(&self, texts: &[String], titles: Option<&Vec<String>>) -> Result<M3Embeddings> {
+        /*
+        Encode a list of texts and/or titles into their three representations
+        */
+        let tokens = self.core.tokenize_documents(texts, titles)?;
+        self.embed(&tokens)
+    }
+
+    pub fn encode_queries(&self, queries: &[String]) -> Result<M3Embeddings> {
+        /*
+        Encode a list of queries into their three representations
+        */
+        let tokens = self.core.tokenize_queries(queries)?;
+        self.embed(&tokens)
+    }
+
+    fn embed(&self, tokens: &[Encoding]) -> Result<M3Embeddings> {
+        let (hidden_state, attention_mask) = self.core.forward(tokens)?;
+        let hidden_state = hidden_state.to_dtype(DType::F32)?;
+
+        let dense = normalize_l2(&cls_pooling(&hidden_state)?)?;
+
+        let weights = self.sparse_linear.forward(&hidden_state)?.relu()?.squeeze(2)?.to_vec2::<f32>()?;
+        let sparse = tokens
+            .iter()
+            .zip(weights.iter())
+            .map(|(encoding, weights)| self.lexical_weights(encoding, weights))
+            .collect();
+
+        // Every token but [CLS] gets a vector, normalized so that MaxSim is a sum of cosine similarities
+        let vectors = self.colbert_linear.forward(&hidden_state.i((.., 1..))?)?;
+        let vectors = vectors.broadcast_div(&vectors.sqr()?.sum_keepdim(D::Minus1)?.sqrt()?)?;
+        let lengths = attention_mask.to_dtype(DType::F32)?.sum(1)?.to_vec1::<f32>()?;
+        let multi_vector = lengths
+            .iter()
+            .enumerate()
+            .map(|(i, length)| Ok(vectors.i(i)?.narrow(0, 0, (*length as usize).saturating_sub(1))?))
+            .collect::<Result<Vec<Tensor>>>()?;
+
+        Ok(M3Embeddings { dense, sparse, multi_vector })
+    }
+
+    fn lexical_weights(&self, encoding: &Encoding, weights: &[f32]) -> HashMap<String, f32> {
+        /*
+        Keep the highest weight of each non-special token
+        */
+        let mut term_weights: HashMap<String, f32> = HashMap::new();
+        let positions = encoding.get_ids().iter().zip(encoding.get_attention_mask()).zip(weights);
+
+        for ((&id, &mask), &weight) in positions {
+            if mask == 0 || weight <= 0.0 || self.special_token_ids.contains(&id) {
+                continue;
+            }
+            if let Some(token) = self.core.tokenizer().id_to_token(id) {
+                let entry = term_weights.entry(token).or_insert(weight);
+                *entry = entry.max(weight);
+            }
+        }
+        term_weights
+    }
+}
+
+impl DocumentEncoder for BgeM3Encoder {
+    fn new(
+        model_name: &str,
+        revision: &str,
+    ) -> Result<BgeM3Encoder> {
+        Self::from_source(&ModelSource::new(model_name, revision))
+    }
+
+    fn encode(
+        &self,
+        texts: &Vec<String>,
+        titles: Option<&Vec<String>>,
+        _pooling: Pooling,
+        _normalize: bool,
+    ) -> Result<Tensor> {
+        /*
+        Encode a list of texts and/or titles into their normalized [CLS] embeddings
+        */
+        Ok(self.encode_documents(texts, titles)?.dense)
+    }
+
+    fn token_lengths(&self, texts: &[String], titles: Option<&Vec<String>>) -> Result<Vec<usize>> {
+        self.core.token_lengths(texts, titles)
+    }
+}
+
+impl SparseDocumentEncoder for BgeM3Encoder {
+    fn new(
+        model_name: &str,
+        revision: &str,
+    ) -> Result<BgeM3Encoder> {
+        Self::from_source(&ModelSource::new(model_name, revision))
+    }
+
+    fn encode(
+        &self,
+        texts: &Vec<String>,
+        titles: Option<&Vec<String>>,
+    ) -> Result<Vec<HashMap<String, f32>>> {
+        Ok(self.encode_documents(texts, titles)?.sparse)
+    }
+}
+
+impl MultiVectorDocumentEncoder for BgeM3Encoder {
+    fn new(
+        model_name: &str,
+        revision: &str,
+    ) -> Result<BgeM3Encoder> {
+        Self::from_source(&ModelSource::new(model_name, revision))
+    }
+
+    fn encode(
+        &self,
+        texts: &Vec<String>,
+        titles: Option<&Vec<String>>,
+    ) -> Result<Vec<Tensor>> {
+        Ok(self.encode_documents(texts, titles)?.multi_vector)
+    }
+}
+
+impl QueryEncoder for BgeM3Encoder {
+    fn new(
+        model_name: &str,
+        revision: &str,
+    ) -> Result<Self> {
+        Self::from_source(&ModelSource::new(model_name, revision))
+    }
+
+    fn encode(&self, queries: QueryType, _pooling: Pooling, _normalize: bool) -> Result<Tensor> {
+        let queries = match queries {
+            QueryType::Query { query } => vec![query],
+            QueryType::Queries { query } => query,
+        };
+
+        Ok(self.encode_queries(&queries)?.dense)
+    }
+}
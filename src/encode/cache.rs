@@ -0,0 +1,176 @@
+use crate::encode::config::EncoderConfig;
+use crate::encode::pooling::Pooling;
+use crate::encode::source::ModelSource;
+use crate::error::{Error, Result};
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// An on-disk cache of document embeddings addressed by the content of the documents
+/// Each combination of model, tokenizer, revisions, weights, pooling, normalization and EncoderConfig gets its own
+/// append-only file in the cache directory, holding (document hash, embedding) records, so a document whose
+/// title and text did not change since a previous run is never encoded again
+/// Pin the revision to a commit for the cached embeddings to stay identical to a fresh run when a branch moves
+pub struct EmbeddingCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<u128, Vec<f32>>>,
+    file: Mutex<BufWriter<File>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+fn fnv1a_128(bytes: &[u8]) -> u128 {
+    /*
+    128-bit FNV-1a, a hash that stays the same across platforms and Rust releases
+    */
+    const OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013B;
+
+    bytes
+        .iter()
+        .fold(OFFSET_BASIS, |hash, byte| (hash ^ *byte as u128).wrapping_mul(PRIME))
+}
+
+impl EmbeddingCache {
+    pub fn open(
+        cache_dir: impl AsRef<Path>,
+        model_source: &ModelSource,
+        tokenizer_source: &ModelSource,
+        pooling: Pooling,
+        normalize: bool,
+        config: &EncoderConfig,
+    ) -> Result<Self> {
+        /*
+        Open, or create, the cache of an encoder setup inside cache_dir and load its embeddings
+        */
+        let namespace = format!(
+            "{}@{} {:?} {:?} {}@{} {:?} {} {} {}",
+            model_source.model_name_or_path,
+            model_source.revision,
+            model_source.dtype,
+            model_source.weights_file,
+            tokenizer_source.model_name_or_path,
+            tokenizer_source.revision,
+            tokenizer_source.weights_file,
+            pooling,
+            normalize,
+            serde_json::to_string(config)?
+        );
+        std::fs::create_dir_all(&cache_dir)?;
+        let path = cache_dir.as_ref().join(format!("{:032x}.bin", fnv1a_128(namespace.as_bytes())));
+
+        let (entries, length) = if path.exists() { Self::load(&path)? } else { (HashMap::new(), 0) };
+
+        /* Drop a record cut short by an interrupted run, the next records would otherwise be read from its bytes */
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.set_len(length)?;
+
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+            file: Mutex::new(BufWriter::new(file)),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        })
+    }
+
+    fn load(path: &Path) -> Result<(HashMap<u128, Vec<f32>>, u64)> {
+        /*
+        Read the (16 byte hash, u32 dimension, f32 values) records, ignoring a record cut short by an interrupted run,
+        and return them with the length in bytes of the complete records
+        */
+        let bytes = std::fs::read(path)?;
+        let mut entries = HashMap::new();
+        let mut position = 0;
+
+        while position + 20 <= bytes.len() {
+            let key = u128::from_le_bytes(bytes[position..position + 16].try_into().unwrap_or_default());
+            let dimension = u32::from_le_bytes(bytes[position + 16..position + 20].try_into().unwrap_or_default()) as usize;
+            let end = position + 20 + dimension * 4;
+            if end > bytes.len() {
+                break;
+            }
+
+            let embedding = bytes[position + 20..end]
+                .chunks_exact(4)
+                .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+                .collect();
+            entries.insert(key, embedding);
+            position = end;
+        }
+
+        Ok((entries, position as u64))
+    }
+
+    pub fn document_key(title: Option<&str>, text: &str) -> u128 {
+        /*
+        The content address of a document, its title and text
+        */
+        let mut bytes = Vec::with_capacity(text.len() + title.map(str::len).unwrap_or(0) + 1);
+        if let Some(title) = title {
+            bytes.extend_from_slice(title.as_bytes());
+        }
+        bytes.push(0);
+        bytes.extend_from_slice(text.as_bytes());
+        fnv1a_128(&bytes)
+    }
+
+    pub fn get(&self, key: u128) -> Option<Vec<f32>> {
+        let embedding = self.entries.lock().ok()?.get(&key).cloned();
+        match embedding {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        embedding
+    }
+
+    pub fn insert(&self, key: u128, embedding: &[f32]) -> Result<()> {
+        /*
+        Store an embedding in memory and append it to the cache file
+        */
+        let poisoned = || Error::Internal("The embedding cache lock was poisoned".to_string());
+
+        let mut file = self.file.lock().map_err(|_| poisoned())?;
+        file.write_all(&key.to_le_bytes())?;
+        file.write_all(&(embedding.len() as u32).to_le_bytes())?;
+        for value in embedding {
+            file.write_all(&value.to_le_bytes())?;
+        }
+
+        self.entries.lock().map_err(|_| poisoned())?.insert(key, embedding.to_vec());
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| Error::Internal("The embedding cache lock was poisoned".to_string()))?;
+        file.flush()?;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().map(|entries| entries.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
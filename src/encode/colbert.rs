@@ -0,0 +1,314 @@
+use crate::encode::auto::{load_var_builder, OutputModelType};
+use crate::encode::base::MultiVectorDocumentEncoder;
+use crate::encode::config::EncoderConfig;
+use crate::encode::core::EncoderCore;
+use crate::encode::source::ModelSource;
+use crate::error::{Error, Result};
+
+use candle_core::{DType, Module, Tensor};
+use candle_nn::Linear;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use tokenizers::{PaddingParams, PaddingStrategy, Tokenizer, TruncationParams};
+
+pub const COLBERT_CONFIG_FILE: &str = "colbert_config.json";
+
+// The token ids and attention masks of a tokenized batch
+type TokenBatch = (Vec<Vec<u32>>, Vec<Vec<u32>>);
+
+/// The ColBERT specific settings, stored next to a ColBERT index so queries are encoded the same way
+/// The defaults follow the ColBERTv2 checkpoint
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColBertConfig {
+    pub query_max_length: usize,
+    pub doc_max_length: usize,
+    pub query_marker: String,
+    pub document_marker: String,
+    pub attend_to_mask_tokens: bool,
+    pub skip_punctuation: bool,
+}
+
+impl Default for ColBertConfig {
+    fn default() -> Self {
+        Self {
+            query_max_length: 32,
+            doc_max_length: 180,
+            query_marker: "[unused0]".to_string(),
+            document_marker: "[unused1]".to_string(),
+            attend_to_mask_tokens: false,
+            skip_punctuation: true,
+        }
+    }
+}
+
+impl ColBertConfig {
+    pub fn with_max_lengths(mut self, query_max_length: usize, doc_max_length: usize) -> Self {
+        /*
+        Set the number of query tokens, padded with [MASK] up to it, and the maximum number of document tokens
+        */
+        self.query_max_length = query_max_length;
+        self.doc_max_length = doc_max_length;
+        self
+    }
+
+    pub fn with_attend_to_mask_tokens(mut self, attend_to_mask_tokens: bool) -> Self {
+        self.attend_to_mask_tokens = attend_to_mask_tokens;
+        self
+    }
+
+    pub fn with_skip_punctuation(mut self, skip_punctuation: bool) -> Self {
+        /*
+        Drop the vectors of punctuation tokens from documents, as ColBERT does with its skiplist
+        */
+        self.skip_punctuation = skip_punctuation;
+        self
+    }
+
+    pub fn save(&self, index_dir: impl AsRef<Path>) -> Result<()> {
+        let path = index_dir.as_ref().join(COLBERT_CONFIG_FILE);
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(index_dir: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = index_dir.as_ref().join(COLBERT_CONFIG_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let config = std::fs::read_to_string(path)?;
+        Ok(Some(serde_json::from_str(&config)?))
+    }
+}
+
+/// A ColBertEncoder keeps one projected and L2 normalized vector per token instead of pooling them
+/// Queries get the [Q] marker and are padded with [MASK] tokens up to query_max_length (query augmentation),
+/// documents get the [D] marker and lose their padding and punctuation vectors
+/// It follows the tokenization and encoding of https://github.com/stanford-futuredata/ColBERT
+pub struct ColBertEncoder {
+    core: EncoderCore,
+    linear: Linear,
+    dimension: usize,
+    config: ColBertConfig,
+    query_tokenizer: Tokenizer,
+    document_tokenizer: Tokenizer,
+    skiplist: HashSet<u32>,
+}
+
+impl ColBertEncoder {
+    pub fn from_source(source: &ModelSource) -> Result<Self> {
+        Self::from_sources(source, source)
+    }
+
+    pub fn from_sources(model_source: &ModelSource, tokenizer_source: &ModelSource) -> Result<Self> {
+        /*
+        The projection is read from the `linear.weight` tensor of the checkpoint
+        */
+        let core = EncoderCore::from_sources(model_source, tokenizer_source, OutputModelType::BertModel)?;
+
+        let vb = load_var_builder(&model_source.weights()?, DType::F32, core.device())?;
+        let weight = vb
+            .get_unchecked("linear.weight")
+            .map_err(|_| Error::ModelLoad("ColBERT checkpoint does not provide a linear.weight projection".to_string()))?;
+        let (dimension, _hidden_size) = weight.dims2()?;
+        let linear = Linear::new(weight, None);
+
+        let config = ColBertConfig::default();
+        let query_tokenizer = core.tokenizer().clone();
+        let document_tokenizer = core.tokenizer().clone();
+        Self {
+            core,
+            linear,
+            dimension,
+            config: config.clone(),
+            query_tokenizer,
+            document_tokenizer,
+            skiplist: HashSet::new(),
+        }
+        .with_colbert_config(config)
+    }
+
+    pub fn with_config(mut self, config: EncoderConfig) -> Result<Self> {
+        /*
+        Set the templates and field caps used to build the texts, the lengths come from the ColBertConfig
+        */
+        self.core = self.core.with_config(config)?;
+        let colbert_config = self.config.clone();
+        self.with_colbert_config(colbert_config)
+    }
+
+    pub fn with_colbert_config(mut self, config: ColBertConfig) -> Result<Self> {
+        /*
+        Set the query and document lengths, markers and skiplist, defaults to ColBertConfig::default()
+        */
+        let tokenizer = self.core.tokenizer();
+        let padding = tokenizer.get_padding().cloned().unwrap_or_default();
+
+        self.query_tokenizer = tokenizer.clone();
+        self.query_tokenizer
+            .with_truncation(Some(TruncationParams { max_length: config.query_max_length, ..Default::default() }))
+            .map_err(|err| Error::Tokenizer(err.to_string()))?
+            .with_padding(Some(PaddingParams {
+                strategy: PaddingStrategy::Fixed(config.query_max_length),
+                ..padding.clone()
+            }));
+
+        self.document_tokenizer = tokenizer.clone();
+        self.document_tokenizer
+            .with_truncation(Some(TruncationParams { max_length: config.doc_max_length, ..Default::default() }))
+            .map_err(|err| Error::Tokenizer(err.to_string()))?
+            .with_padding(Some(PaddingParams { strategy: PaddingStrategy::BatchLongest, ..padding }));
+
+        self.skiplist = if config.skip_punctuation {
+            punctuation_ids(tokenizer)?
+        } else {
+            HashSet::new()
+        };
+        self.config = config;
+        Ok(self)
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    pub fn colbert_config(&self) -> &ColBertConfig {
+        &self.config
+    }
+
+    fn token_id(&self, token: &str) -> Result<u32> {
+        self.core
+            .tokenizer()
+            .token_to_id(token)
+            .ok_or(Error::Tokenizer(format!("The tokenizer has no {} token", token)))
+    }
+
+    fn tokenize(&self, tokenizer: &Tokenizer, texts: Vec<String>, marker: &str) -> Result<TokenBatch> {
+        /*
+        Tokenize ". text" and overwrite the placeholder after [CLS] with the marker, returning ids and attention masks
+        */
+        let marker_id = self.token_id(marker)?;
+        let texts: Vec<String> = texts.into_iter().map(|text| format!(". {}", text)).collect();
+        let encodings = tokenizer
+            .encode_batch(texts, true)
+            .map_err(|err| Error::Tokenizer(err.to_string()))?;
+
+        let ids = encodings
+            .iter()
+            .map(|encoding| {
+                let mut ids = encoding.get_ids().to_vec();
+                if ids.len() > 1 {
+                    ids[1] = marker_id;
+                }
+                ids
+            })
+            .collect();
+        let masks = encodings.iter().map(|encoding| encoding.get_attention_mask().to_vec()).collect();
+
+        Ok((ids, masks))
+    }
+
+    fn project(&self, ids: &[Vec<u32>], masks: &[Vec<u32>]) -> Result<Tensor> {
+        /*
+        Run the encoder and the linear projection, returning (batch, seq_len, dimension) L2 normalized vectors
+        */
+        let device = self.core.device();
+        let token_ids = Tensor::new(ids.to_vec(), device)?;
+        let attention_mask = Tensor::new(masks.to_vec(), device)?;
+        let token_type_ids = token_ids.zeros_like()?;
+
+        let hidden_state = self
+            .core
+            .model()
+            .forward(&token_ids, &token_type_ids, &attention_mask)?
+            .to_dtype(DType::F32)?;
+        let vectors = self.linear.forward(&hidden_state)?;
+        let norms = vectors.sqr()?.sum_keepdim(2)?.sqrt()?.clamp(1e-12, f32::MAX)?;
+
+        Ok(vectors.broadcast_div(&norms)?)
+    }
+
+    pub fn encode_queries(&self, queries: &[String]) -> Result<Vec<Tensor>> {
+        /*
+        Encode queries into (query_max_length, dimension) tensors, [MASK] augmentation vectors included
+        */
+        let mask_id = self.token_id("[MASK]")?;
+        let pad_id = self.query_tokenizer.get_padding().map(|padding| padding.pad_id).unwrap_or(0);
+
+        let queries: Vec<String> = queries.iter().map(|query| self.core.config().format_query(query)).collect();
+        let (mut ids, mut masks) = self.tokenize(&self.query_tokenizer, queries, &self.config.query_marker)?;
+        for (ids, mask) in ids.iter_mut().zip(masks.iter_mut()) {
+            for (id, mask) in ids.iter_mut().zip(mask.iter_mut()) {
+                if *id == pad_id && *mask == 0 {
+                    *id = mask_id;
+                    if self.config.attend_to_mask_tokens {
+                        *mask = 1;
+                    }
+                }
+            }
+        }
+
+        let vectors = self.project(&ids, &masks)?;
+        Ok((0..ids.len()).map(|i| vectors.get(i)).collect::<candle_core::Result<Vec<_>>>()?)
+    }
+
+    pub fn encode_documents(&self, texts: &[String], titles: Option<&Vec<String>>) -> Result<Vec<Tensor>> {
+        /*
+        Encode documents into (tokens, dimension) tensors, without their padding and skiplist vectors
+        */
+        let texts = self.core.config().join_fields(self.core.tokenizer(), texts, titles)?;
+        let (ids, masks) = self.tokenize(&self.document_tokenizer, texts, &self.config.document_marker)?;
+        let vectors = self.project(&ids, &masks)?;
+
+        ids.iter()
+            .zip(masks.iter())
+            .enumerate()
+            .map(|(i, (ids, mask))| {
+                let kept: Vec<u32> = ids
+                    .iter()
+                    .zip(mask.iter())
+                    .enumerate()
+                    .filter(|(_, (id, mask))| **mask == 1 && !self.skiplist.contains(id))
+                    .map(|(position, _)| position as u32)
+                    .collect();
+                let kept = Tensor::new(kept, vectors.device())?;
+                Ok(vectors.get(i)?.index_select(&kept, 0)?)
+            })
+            .collect()
+    }
+}
+
+fn punctuation_ids(tokenizer: &Tokenizer) -> Result<HashSet<u32>> {
+    /*
+    The token ids of the ASCII punctuation symbols, the skiplist of ColBERT documents
+    */
+    let mut ids = HashSet::new();
+    for symbol in "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~".chars() {
+        let encoding = tokenizer
+            .encode(symbol.to_string(), false)
+            .map_err(|err| Error::Tokenizer(err.to_string()))?;
+        if let Some(id) = encoding.get_ids().first() {
+            ids.insert(*id);
+        }
+    }
+    Ok(ids)
+}
+
+impl MultiVectorDocumentEncoder for ColBertEncoder {
+    // instantiating a new ColBertEncoder instance
+    fn new(
+        model_name: &str,
+        revision: &str,
+    ) -> Result<ColBertEncoder> {
+        Self::from_source(&ModelSource::new(model_name, revision))
+    }
+
+    fn encode(
+        &self,
+        texts: &Vec<String>,
+        titles: Option<&Vec<String>>,
+    ) -> Result<Vec<Tensor>> {
+        self.encode_documents(texts, titles)
+    }
+}
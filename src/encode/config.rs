@@ -0,0 +1,447 @@
+use crate::error::{Error, Result};
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use tokenizers::{EncodeInput, Encoding, Tokenizer, TruncationDirection, TruncationParams};
+
+/// The file an EncoderConfig is persisted to inside an index directory
+pub const ENCODER_CONFIG_FILE: &str = "encoder_config.json";
+
+/// Which sequence loses tokens when a (title, text) pair exceeds the maximum length
+/// Single sequences are always truncated on their own, whatever the strategy
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncationStrategy {
+    #[default]
+    LongestFirst,
+    OnlyFirst,
+    OnlySecond,
+}
+
+impl FromStr for TruncationStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "longest_first" | "longest-first" => Ok(TruncationStrategy::LongestFirst),
+            "only_first" | "only-first" => Ok(TruncationStrategy::OnlyFirst),
+            "only_second" | "only-second" => Ok(TruncationStrategy::OnlySecond),
+            _ => Err(Error::Config(format!(
+                "Unknown truncation strategy {:?}, expected one of longest_first, only_first, only_second",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for TruncationStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TruncationStrategy::LongestFirst => "longest_first",
+            TruncationStrategy::OnlyFirst => "only_first",
+            TruncationStrategy::OnlySecond => "only_second",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl From<TruncationStrategy> for tokenizers::TruncationStrategy {
+    fn from(strategy: TruncationStrategy) -> Self {
+        match strategy {
+            TruncationStrategy::LongestFirst => tokenizers::TruncationStrategy::LongestFirst,
+            TruncationStrategy::OnlyFirst => tokenizers::TruncationStrategy::OnlyFirst,
+            TruncationStrategy::OnlySecond => tokenizers::TruncationStrategy::OnlySecond,
+        }
+    }
+}
+
+/// The side tokens are dropped from when a sequence is too long
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncationSide {
+    Left,
+    #[default]
+    Right,
+}
+
+impl FromStr for TruncationSide {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "left" => Ok(TruncationSide::Left),
+            "right" => Ok(TruncationSide::Right),
+            _ => Err(Error::Config(format!(
+                "Unknown truncation side {:?}, expected left or right",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for TruncationSide {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TruncationSide::Left => write!(f, "left"),
+            TruncationSide::Right => write!(f, "right"),
+        }
+    }
+}
+
+impl From<TruncationSide> for TruncationDirection {
+    fn from(side: TruncationSide) -> Self {
+        match side {
+            TruncationSide::Left => TruncationDirection::Left,
+            TruncationSide::Right => TruncationDirection::Right,
+        }
+    }
+}
+
+/// How a title and a text are combined into the model input
+/// DPR-family checkpoints are trained on [SEP]-separated pairs, most other bi-encoders on "title text"
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldJoin {
+    #[default]
+    Space,
+    Pair,
+    Separator(String),
+}
+
+impl FromStr for FieldJoin {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "space" => Ok(FieldJoin::Space),
+            "pair" => Ok(FieldJoin::Pair),
+            _ => match s.strip_prefix("separator:") {
+                Some(separator) => Ok(FieldJoin::Separator(separator.to_string())),
+                None => Err(Error::Config(format!(
+                    "Unknown field join {:?}, expected space, pair or separator:<string>",
+                    s
+                ))),
+            },
+        }
+    }
+}
+
+impl fmt::Display for FieldJoin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldJoin::Space => write!(f, "space"),
+            FieldJoin::Pair => write!(f, "pair"),
+            FieldJoin::Separator(separator) => write!(f, "separator:{}", separator),
+        }
+    }
+}
+
+/// Tokenization settings shared by the document and query encoders
+/// It mirrors the max_length argument of Pyserini's encoders, with separate caps for titles and texts,
+/// and the query/passage prefixes that instruction-tuned embedding models such as E5 or BGE expect
+/// It also holds the Matryoshka dimension embeddings are truncated to, so queries match the index
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncoderConfig {
+    pub max_length: usize,
+    pub truncation_strategy: TruncationStrategy,
+    pub truncation_side: TruncationSide,
+    pub max_title_length: Option<usize>,
+    pub max_text_length: Option<usize>,
+    pub field_join: FieldJoin,
+    pub query_template: Option<String>,
+    pub document_template: Option<String>,
+    pub truncate_dim: Option<usize>,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        Self {
+            max_length: 512,
+            truncation_strategy: TruncationStrategy::LongestFirst,
+            truncation_side: TruncationSide::Right,
+            max_title_length: None,
+            max_text_length: None,
+            field_join: FieldJoin::Space,
+            query_template: None,
+            document_template: None,
+            truncate_dim: None,
+        }
+    }
+}
+
+impl EncoderConfig {
+    pub fn new(max_length: usize) -> Self {
+        Self {
+            max_length,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_truncation(mut self, strategy: TruncationStrategy, side: TruncationSide) -> Self {
+        self.truncation_strategy = strategy;
+        self.truncation_side = side;
+        self
+    }
+
+    pub fn with_max_title_length(mut self, max_title_length: usize) -> Self {
+        self.max_title_length = Some(max_title_length);
+        self
+    }
+
+    pub fn with_max_text_length(mut self, max_text_length: usize) -> Self {
+        self.max_text_length = Some(max_text_length);
+        self
+    }
+
+    pub fn with_field_join(mut self, field_join: FieldJoin) -> Self {
+        self.field_join = field_join;
+        self
+    }
+
+    pub fn with_query_template(mut self, template: impl Into<String>) -> Self {
+        self.query_template = Some(template.into());
+        self
+    }
+
+    pub fn with_document_template(mut self, template: impl Into<String>) -> Self {
+        self.document_template = Some(template.into());
+        self
+    }
+
+    pub fn with_truncate_dim(mut self, truncate_dim: usize) -> Self {
+        /*
+        Keep only the first truncate_dim dimensions of the embeddings, for Matryoshka models
+        */
+        self.truncate_dim = Some(truncate_dim);
+        self
+    }
+
+    pub fn is_pair(&self) -> bool {
+        self.field_join == FieldJoin::Pair
+    }
+
+    pub fn truncation_params(&self, pair: bool) -> TruncationParams {
+        /*
+        Build the tokenizer truncation parameters, the pair strategies only apply to sentence pairs
+        */
+        let strategy = if pair {
+            self.truncation_strategy
+        } else {
+            TruncationStrategy::LongestFirst
+        };
+
+        TruncationParams {
+            max_length: self.max_length,
+            strategy: strategy.into(),
+            direction: self.truncation_side.into(),
+            stride: 0,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        /*
+        Reject the combinations of settings that cannot be honoured
+        In pair mode the title is the first segment, so a document template can only place {text}
+        */
+        if self.max_length == 0 {
+            return Err(Error::Config("max_length must be greater than zero".to_string()));
+        }
+        if let (FieldJoin::Pair, Some(template)) = (&self.field_join, &self.document_template) {
+            if template.contains("{title}") {
+                return Err(Error::Config(format!(
+                    "The document template {:?} places {{title}}, which the pair field join already encodes as the first segment",
+                    template
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, tokenizer: &mut Tokenizer, pair: bool) -> Result<()> {
+        /*
+        Set the truncation of a tokenizer according to this configuration
+        */
+        self.validate()?;
+        tokenizer
+            .with_truncation(Some(self.truncation_params(pair)))
+            .map_err(|err| Error::Tokenizer(err.to_string()))?;
+        Ok(())
+    }
+
+    pub fn truncate_field(&self, tokenizer: &Tokenizer, field: &str, max_tokens: Option<usize>) -> Result<String> {
+        /*
+        Cut a title or a text down to at most max_tokens tokens, keeping the original characters
+        */
+        let max_tokens = match max_tokens {
+            Some(max_tokens) => max_tokens,
+            None => return Ok(field.to_string()),
+        };
+
+        let mut tokenizer = tokenizer.clone();
+        tokenizer
+            .with_truncation(None)
+            .map_err(|err| Error::Tokenizer(err.to_string()))?;
+        let encoding = tokenizer
+            .encode(field, false)
+            .map_err(|err| Error::Tokenizer(err.to_string()))?;
+
+        let offsets = encoding.get_offsets();
+        if offsets.len() <= max_tokens {
+            return Ok(field.to_string());
+        }
+        if max_tokens == 0 {
+            return Ok(String::new());
+        }
+
+        let truncated = match self.truncation_side {
+            TruncationSide::Right => &field[..offsets[max_tokens - 1].1],
+            TruncationSide::Left => &field[offsets[offsets.len() - max_tokens].0..],
+        };
+        Ok(truncated.trim().to_string())
+    }
+
+    pub fn format_document(&self, title: Option<&str>, text: &str) -> String {
+        /*
+        Combine a title and a text following the field join and the document template
+        A template with a {title} placeholder places the title itself, otherwise the joined fields fill {text}
+        */
+        match &self.document_template {
+            Some(template) if template.contains("{title}") => template
+                .replace("{title}", title.unwrap_or_default())
+                .replace("{text}", text)
+                .trim()
+                .to_string(),
+            template => {
+                let joined = match (title, &self.field_join) {
+                    (Some(title), FieldJoin::Separator(separator)) => format!("{}{}{}", title, separator, text),
+                    (Some(title), _) => format!("{} {}", title, text),
+                    (None, _) => text.to_string(),
+                };
+                match template {
+                    Some(template) => template.replace("{text}", &joined),
+                    None => joined,
+                }
+            }
+        }
+    }
+
+    pub fn format_query(&self, query: &str) -> String {
+        /*
+        Fill the query template, e.g. "query: {text}" for E5 models
+        */
+        match &self.query_template {
+            Some(template) => template.replace("{text}", query),
+            None => query.to_string(),
+        }
+    }
+
+    pub fn join_fields(
+        &self,
+        tokenizer: &Tokenizer,
+        texts: &[String],
+        titles: Option<&Vec<String>>,
+    ) -> Result<Vec<String>> {
+        /*
+        Apply the per-field caps and the document template, joining titles and texts into single sequences
+        */
+        match titles {
+            Some(titles) => texts
+                .iter()
+                .zip(titles.iter())
+                .map(|(text, title)| {
+                    let title = self.truncate_field(tokenizer, title, self.max_title_length)?;
+                    let text = self.truncate_field(tokenizer, text, self.max_text_length)?;
+                    Ok(self.format_document(Some(&title), &text))
+                })
+                .collect(),
+            None => texts
+                .iter()
+                .map(|text| {
+                    let text = self.truncate_field(tokenizer, text, self.max_text_length)?;
+                    Ok(self.format_document(None, &text))
+                })
+                .collect(),
+        }
+    }
+
+    pub fn encode_inputs(
+        &self,
+        tokenizer: &Tokenizer,
+        texts: &[String],
+        titles: Option<&Vec<String>>,
+    ) -> Result<Vec<EncodeInput<'static>>> {
+        /*
+        Build the tokenizer inputs, (title, text) sentence pairs in pair mode and joined strings otherwise
+        */
+        self.validate()?;
+        match (titles, &self.field_join) {
+            (Some(titles), FieldJoin::Pair) => texts
+                .iter()
+                .zip(titles.iter())
+                .map(|(text, title)| {
+                    let title = self.truncate_field(tokenizer, title, self.max_title_length)?;
+                    let text = self.truncate_field(tokenizer, text, self.max_text_length)?;
+                    Ok((title, self.format_document(None, &text)).into())
+                })
+                .collect(),
+            _ => Ok(self
+                .join_fields(tokenizer, texts, titles)?
+                .into_iter()
+                .map(|text| text.into())
+                .collect()),
+        }
+    }
+
+    pub fn query_inputs(&self, tokenizer: &Tokenizer, queries: &[String]) -> Result<Vec<EncodeInput<'static>>> {
+        /*
+        Build the tokenizer inputs for a batch of queries, capped like texts and filled into the query template
+        */
+        queries
+            .iter()
+            .map(|query| {
+                let query = self.truncate_field(tokenizer, query, self.max_text_length)?;
+                Ok(self.format_query(&query).into())
+            })
+            .collect()
+    }
+
+    pub fn save(&self, index_dir: impl AsRef<Path>) -> Result<()> {
+        /*
+        Store the configuration next to an index so queries are encoded the same way at search time
+        */
+        let path = index_dir.as_ref().join(ENCODER_CONFIG_FILE);
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(index_dir: impl AsRef<Path>) -> Result<Option<Self>> {
+        /*
+        Read the configuration stored next to an index, if there is one
+        */
+        let path = index_dir.as_ref().join(ENCODER_CONFIG_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let config = std::fs::read_to_string(path)?;
+        Ok(Some(serde_json::from_str(&config)?))
+    }
+
+    pub fn tokenize(&self, tokenizer: &Tokenizer, inputs: Vec<EncodeInput<'static>>) -> Result<Vec<Encoding>> {
+        /*
+        Tokenize a batch, falling back to single sequence truncation when a pair strategy meets single inputs
+        */
+        let single = inputs.iter().any(|input| matches!(input, EncodeInput::Single(_)));
+        let encodings = if single && self.is_pair() && self.truncation_strategy == TruncationStrategy::OnlySecond {
+            let mut tokenizer = tokenizer.clone();
+            self.apply(&mut tokenizer, false)?;
+            tokenizer.encode_batch(inputs, true)
+        } else {
+            tokenizer.encode_batch(inputs, true)
+        };
+
+        encodings.map_err(|err| Error::Tokenizer(err.to_string()))
+    }
+}
@@ -0,0 +1,146 @@
+use crate::encode::auto::{batch_tensors, build_model, load_tokenizer, Model, OutputModelType};
+use crate::encode::config::EncoderConfig;
+use crate::encode::pooling::{truncate_embeddings, Pooling};
+use crate::encode::registry::ModelRegistry;
+use crate::encode::source::ModelSource;
+use crate::encode::splade::splade_encode;
+use crate::error::{Error, Result};
+
+use candle_core::{DType, Device, Tensor};
+use std::collections::HashMap;
+use tokenizers::{Encoding, Tokenizer};
+
+/// The tokenize -> forward -> pool pipeline shared by the document and query encoders
+/// The model and the tokenizer are loaded from independent sources, so a question encoder can
+/// borrow the tokenizer of its context encoder or of any other repository
+pub struct EncoderCore {
+    model: Model,
+    tokenizer: Tokenizer,
+    device: Device,
+    config: EncoderConfig,
+}
+
+impl EncoderCore {
+    pub fn from_sources(
+        model_source: &ModelSource,
+        tokenizer_source: &ModelSource,
+        output_model_type: OutputModelType,
+    ) -> Result<Self> {
+        /*
+        Load the model from one source and the tokenizer from another, they can be the same
+        Every encoder's from_source(source) is from_sources(source, source), from_sources exists for checkpoints
+        that ship without a tokenizer, e.g. a DPR question encoder borrowing the one of its context encoder
+        */
+        Self::from_sources_with_registry(model_source, tokenizer_source, output_model_type, &ModelRegistry::default())
+    }
+
+    pub fn from_sources_with_registry(
+        model_source: &ModelSource,
+        tokenizer_source: &ModelSource,
+        output_model_type: OutputModelType,
+        registry: &ModelRegistry,
+    ) -> Result<Self> {
+        let device = model_source.device.clone();
+        let model = build_model(model_source, output_model_type, registry)?;
+        let tokenizer = load_tokenizer(tokenizer_source)?;
+        Self::new(model, tokenizer, device)
+    }
+
+    pub fn new(model: Model, tokenizer: Tokenizer, device: Device) -> Result<Self> {
+        let config = EncoderConfig::default();
+        Self { model, tokenizer, device, config: config.clone() }.with_config(config)
+    }
+
+    pub fn with_config(mut self, config: EncoderConfig) -> Result<Self> {
+        /*
+        Set the maximum lengths, truncation and templates used when tokenizing, defaults to EncoderConfig::default()
+        The with_config of the encoders forward here, unless they document their own defaults
+        */
+        config.apply(&mut self.tokenizer, config.is_pair())?;
+        self.config = config;
+        Ok(self)
+    }
+
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    pub fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    pub fn config(&self) -> &EncoderConfig {
+        &self.config
+    }
+
+    pub fn tokenize_documents(&self, texts: &[String], titles: Option<&Vec<String>>) -> Result<Vec<Encoding>> {
+        /*
+        Tokenize documents following the field join, the per-field caps and the document template
+        */
+        let inputs = self.config.encode_inputs(&self.tokenizer, texts, titles)?;
+        self.config.tokenize(&self.tokenizer, inputs)
+    }
+
+    pub fn tokenize_queries(&self, queries: &[String]) -> Result<Vec<Encoding>> {
+        /*
+        Tokenize queries following the text cap and the query template
+        */
+        let inputs = self.config.query_inputs(&self.tokenizer, queries)?;
+        self.config.tokenize(&self.tokenizer, inputs)
+    }
+
+    pub fn token_lengths(&self, texts: &[String], titles: Option<&Vec<String>>) -> Result<Vec<usize>> {
+        /*
+        Count the tokens of each document after truncation, ignoring the batch padding
+        */
+        let tokens = self.tokenize_documents(texts, titles)?;
+        let lengths = tokens
+            .iter()
+            .map(|encoding| encoding.get_attention_mask().iter().filter(|&&mask| mask == 1).count())
+            .collect();
+        Ok(lengths)
+    }
+
+    pub fn forward(&self, tokens: &[Encoding]) -> Result<(Tensor, Tensor)> {
+        /*
+        Run the model over a tokenized batch, returning its output along with the attention mask
+        */
+        let (token_ids, token_type_ids, attention_mask) = batch_tensors(tokens, &self.device)?;
+        let output = self.model.forward(&token_ids, &token_type_ids, &attention_mask)?;
+        Ok((output, attention_mask))
+    }
+
+    pub fn embed(&self, tokens: &[Encoding], pooling: Pooling, normalize: bool) -> Result<Tensor> {
+        /*
+        Pool the hidden states of a tokenized batch into one dense vector per sequence
+        */
+        if self.model.is_masked_lm() {
+            return Err(Error::Config(format!(
+                "{} pooling is not supported over masked language modelling logits",
+                pooling
+            )));
+        }
+        let (hidden_state, attention_mask) = self.forward(tokens)?;
+        // Reduced precision models pool in f32 so the embeddings always come out as f32
+        let hidden_state = hidden_state.to_dtype(DType::F32)?;
+
+        match self.config.truncate_dim {
+            Some(dimension) => {
+                let embeddings = pooling.pool(&hidden_state, &attention_mask, false)?;
+                truncate_embeddings(&embeddings, dimension, normalize)
+            }
+            None => pooling.pool(&hidden_state, &attention_mask, normalize),
+        }
+    }
+
+    pub fn term_weights(&self, tokens: Vec<Encoding>) -> Result<Vec<HashMap<String, f32>>> {
+        /*
+        Turn a tokenized batch into SPLADE term -> weight maps with the masked language modelling head
+        */
+        splade_encode(&self.model, &self.tokenizer, &self.device, tokens)
+    }
+}
@@ -1,9 +1,38 @@
 pub mod auto;
 pub mod base;
+pub mod bge_m3;
+pub mod cache;
+pub mod colbert;
+pub mod config;
+pub mod core;
+pub mod onnx;
+pub mod pipeline;
+pub mod pooling;
+pub mod quantized_bert;
+pub mod registry;
+pub mod sentence_transformers;
+pub mod source;
+pub mod static_embedding;
+pub mod splade;
+pub mod unicoil;
 pub mod vector_writer;
 
 // Path: src/encode/auto.rs
 
 pub use auto::AutoDocumentEncoder;
-pub use base::DocumentEncoder;
-pub use vector_writer::{JsonlCollectionIterator, JsonlRepresentationWriter};
+pub use base::{DocumentEncoder, MultiVectorDocumentEncoder, SparseDocumentEncoder};
+pub use bge_m3::{BgeM3Encoder, M3Embeddings};
+pub use cache::EmbeddingCache;
+pub use colbert::{ColBertConfig, ColBertEncoder};
+pub use config::{EncoderConfig, FieldJoin, TruncationSide, TruncationStrategy};
+pub use core::EncoderCore;
+pub use pipeline::EncodingPipeline;
+pub use pooling::Pooling;
+pub use sentence_transformers::SentenceTransformerEncoder;
+pub use source::ModelSource;
+pub use static_embedding::StaticEmbeddingEncoder;
+pub use splade::SpladeDocumentEncoder;
+pub use unicoil::UniCoilDocumentEncoder;
+pub use vector_writer::{
+    ColBertIndexWriter, JsonVectorCollectionWriter, JsonlCollectionIterator, JsonlRepresentationWriter, M3RepresentationWriter,
+};
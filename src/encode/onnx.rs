@@ -0,0 +1,97 @@
+use crate::encode::auto::OutputModelType;
+use crate::error::{Error, Result};
+
+use candle_core::{DType, Tensor};
+use std::path::Path;
+use tract_onnx::prelude::{Framework, InferenceModelExt, TValue, TVec, Tensor as TractTensor, TypedModel, TypedRunnableModel};
+
+/// An encoder exported to ONNX, e.g. by Optimum, run with the pure-Rust tract runtime
+/// The graph takes int64 `input_ids`, `attention_mask` and optionally `token_type_ids`, and its first output
+/// is either the (batch, seq_len, hidden_size) last hidden state or the (batch, seq_len, vocab_size) MLM logits
+/// Optimized and int8 quantized exports run the same way, no native ONNX Runtime is needed
+pub struct OnnxModel {
+    plan: TypedRunnableModel<TypedModel>,
+    input_names: Vec<String>,
+    masked_lm: bool,
+}
+
+impl OnnxModel {
+    pub fn load(filename: &Path, output_model_type: OutputModelType) -> Result<Self> {
+        /*
+        Parse, optimize and plan an ONNX graph, keeping the symbolic batch and sequence dimensions of the export
+        */
+        let load_error = |err: tract_onnx::prelude::TractError| {
+            Error::ModelLoad(format!("Could not load the ONNX model {}: {}", filename.display(), err))
+        };
+        let model = tract_onnx::onnx()
+            .model_for_path(filename)
+            .and_then(|model| model.into_optimized())
+            .map_err(load_error)?;
+
+        let input_names = model
+            .input_outlets()
+            .map_err(load_error)?
+            .iter()
+            .map(|outlet| model.node(outlet.node).name.clone())
+            .collect::<Vec<String>>();
+        if let Some(name) = input_names
+            .iter()
+            .find(|name| !["input_ids", "attention_mask", "token_type_ids"].contains(&name.as_str()))
+        {
+            return Err(Error::ModelLoad(format!(
+                "Unsupported ONNX input {}, expected input_ids, attention_mask and token_type_ids",
+                name
+            )));
+        }
+
+        let plan = model.into_runnable().map_err(load_error)?;
+
+        Ok(Self {
+            plan,
+            input_names,
+            masked_lm: output_model_type == OutputModelType::BertForMaskedLM,
+        })
+    }
+
+    pub fn is_masked_lm(&self) -> bool {
+        self.masked_lm
+    }
+
+    pub fn forward(&self, token_ids: &Tensor, token_type_ids: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+        /*
+        Run the graph over a padded batch and return its first output as an f32 candle tensor
+        */
+        let (batch_size, seq_len) = token_ids.dims2()?;
+        let to_tract = |tensor: &Tensor| -> Result<TValue> {
+            let values = tensor.to_dtype(DType::I64)?.flatten_all()?.to_vec1::<i64>()?;
+            let tensor = TractTensor::from_shape(&[batch_size, seq_len], &values)
+                .map_err(|err| candle_core::Error::msg(err.to_string()))?;
+            Ok(tensor.into())
+        };
+
+        let inputs = self
+            .input_names
+            .iter()
+            .map(|name| match name.as_str() {
+                "input_ids" => to_tract(token_ids),
+                "attention_mask" => to_tract(attention_mask),
+                _ => to_tract(token_type_ids),
+            })
+            .collect::<Result<TVec<TValue>>>()?;
+
+        let outputs = self
+            .plan
+            .run(inputs)
+            .map_err(|err| candle_core::Error::msg(format!("ONNX inference failed: {}", err)))?;
+        let output = outputs
+            .first()
+            .ok_or_else(|| candle_core::Error::msg("The ONNX model has no output"))?
+            .to_array_view::<f32>()
+            .map_err(|err| candle_core::Error::msg(err.to_string()))?;
+
+        let shape = output.shape().to_vec();
+        let values: Vec<f32> = output.iter().copied().collect();
+
+        Ok(Tensor::from_vec(values, shape, token_ids.device())?)
+    }
+}
@@ -0,0 +1,361 @@
+use crate::encode::base::{DocumentEncoder, RepresentationWriter};
+use crate::encode::cache::EmbeddingCache;
+use crate::encode::pooling::Pooling;
+use crate::encode::vector_writer::JsonlCollectionIterator;
+use crate::error::{Error, Result};
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// An EncodingPipeline encodes a whole collection into a RepresentationWriter
+/// Documents are read in windows of `batch_size * buckets`, sorted by token length inside each window so
+/// batches hold documents of similar lengths, encoded by several workers in parallel, and written back
+/// in their original docid order
+/// With a token budget, batches are cut so that their padded size stays under `max_tokens`
+/// With an EmbeddingCache, only the documents missing from the cache reach the encoder
+pub struct EncodingPipeline {
+    batch_size: usize,
+    num_workers: usize,
+    buckets: usize,
+    max_tokens: Option<usize>,
+    pooling: Pooling,
+    normalize: bool,
+    cache: Option<EmbeddingCache>,
+}
+
+/// The documents of one window, in corpus order
+#[derive(Default)]
+struct Window {
+    ids: Vec<String>,
+    texts: Vec<String>,
+    titles: Vec<String>,
+}
+
+impl Window {
+    fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    fn titles(&self) -> Option<&Vec<String>> {
+        if self.titles.len() == self.texts.len() && !self.titles.is_empty() {
+            Some(&self.titles)
+        } else {
+            None
+        }
+    }
+}
+
+impl EncodingPipeline {
+    pub fn new(batch_size: usize, num_workers: usize) -> Self {
+        /*
+        Create a pipeline encoding batch_size documents at a time on num_workers threads
+        */
+        Self {
+            batch_size: batch_size.max(1),
+            num_workers: num_workers.max(1),
+            buckets: 64,
+            max_tokens: None,
+            pooling: Pooling::Cls,
+            normalize: false,
+            cache: None,
+        }
+    }
+
+    pub fn with_pooling(mut self, pooling: Pooling, normalize: bool) -> Self {
+        /*
+        Set the pooling strategy and L2 normalization of the embeddings, defaults to unnormalized CLS pooling
+        */
+        self.pooling = pooling;
+        self.normalize = normalize;
+        self
+    }
+
+    pub fn with_buckets(mut self, buckets: usize) -> Self {
+        /*
+        Set how many batches are sorted by length together, larger windows pad less but hold more documents in memory
+        */
+        self.buckets = buckets.max(1);
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        /*
+        Bound the padded number of tokens of each batch, batch_size remains the maximum number of documents
+        */
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_cache(mut self, cache: EmbeddingCache) -> Self {
+        /*
+        Reuse the embeddings of unchanged documents and store the new ones, the cache must have been opened
+        with the same encoder, pooling and normalization as the pipeline
+        */
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn cache(&self) -> Option<&EmbeddingCache> {
+        self.cache.as_ref()
+    }
+
+    pub fn run<E, W>(&self, iterator: &mut JsonlCollectionIterator, encoder: &E, writer: &mut W) -> Result<usize>
+    where
+        E: DocumentEncoder + Sync,
+        W: RepresentationWriter,
+    {
+        /*
+        Encode every document of the iterator and hand the embeddings to the writer, returning the number of documents
+        */
+        let window_size = self.batch_size * self.buckets;
+        let mut window = Window::default();
+        let mut total = 0;
+
+        for batch in iterator.iter() {
+            let size = batch["id"].len();
+            window.ids.extend(batch["id"].iter().cloned());
+            window.texts.extend(batch["text"].iter().cloned());
+            if let Some(titles) = batch.get("title").filter(|titles| titles.len() == size) {
+                window.titles.extend(titles.iter().cloned());
+            }
+
+            if window.len() >= window_size {
+                total += self.process_window(std::mem::take(&mut window), encoder, writer)?;
+            }
+        }
+        if window.len() > 0 {
+            total += self.process_window(window, encoder, writer)?;
+        }
+        if let Some(cache) = &self.cache {
+            cache.flush()?;
+        }
+
+        Ok(total)
+    }
+
+    fn process_window<E, W>(&self, window: Window, encoder: &E, writer: &mut W) -> Result<usize>
+    where
+        E: DocumentEncoder + Sync,
+        W: RepresentationWriter,
+    {
+        /*
+        Sort a window by token length, encode its batches in parallel and write them back in corpus order
+        Documents found in the cache skip the encoder
+        */
+        let titles = window.titles();
+        let mut rows: Vec<Vec<f32>> = vec![Vec::new(); window.len()];
+
+        let keys: Vec<u128> = match &self.cache {
+            Some(_) => (0..window.len())
+                .map(|i| EmbeddingCache::document_key(titles.map(|titles| titles[i].as_str()), &window.texts[i]))
+                .collect(),
+            None => Vec::new(),
+        };
+        let mut pending: Vec<usize> = Vec::new();
+        for (i, row) in rows.iter_mut().enumerate() {
+            match self.cache.as_ref().and_then(|cache| cache.get(keys[i])) {
+                Some(embedding) => *row = embedding,
+                None => pending.push(i),
+            }
+        }
+
+        if !pending.is_empty() {
+            let pending_texts: Vec<String> = pending.iter().map(|&i| window.texts[i].clone()).collect();
+            let pending_titles: Option<Vec<String>> =
+                titles.map(|titles| pending.iter().map(|&i| titles[i].clone()).collect());
+            let lengths = encoder.token_lengths(&pending_texts, pending_titles.as_ref())?;
+            let mut order: Vec<usize> = (0..pending.len()).collect();
+            order.sort_by_key(|&i| lengths[i]);
+
+            let batches: Vec<Vec<usize>> = match self.max_tokens {
+                Some(max_tokens) => budget_batches(&order, &lengths, max_tokens, self.batch_size),
+                None => order.chunks(self.batch_size).map(|batch| batch.to_vec()).collect(),
+            };
+            let batches: Vec<Vec<usize>> = batches
+                .into_iter()
+                .map(|batch| batch.into_iter().map(|position| pending[position]).collect())
+                .collect();
+            let embeddings = self.encode_batches(&batches, &window, encoder)?;
+
+            for (batch, batch_embeddings) in batches.iter().zip(embeddings) {
+                for (&i, row) in batch.iter().zip(batch_embeddings) {
+                    if let Some(cache) = &self.cache {
+                        cache.insert(keys[i], &row)?;
+                    }
+                    rows[i] = row;
+                }
+            }
+        }
+
+        for start in (0..window.len()).step_by(self.batch_size) {
+            let end = usize::min(start + self.batch_size, window.len());
+
+            let mut batch_info = HashMap::new();
+            batch_info.insert("id", window.ids[start..end].to_vec());
+            batch_info.insert("text", window.texts[start..end].to_vec());
+            if let Some(titles) = titles {
+                batch_info.insert("title", titles[start..end].to_vec());
+            }
+
+            let mut embeddings: Vec<f32> = rows[start..end].concat();
+            writer.write(&batch_info, &mut embeddings)?;
+        }
+
+        Ok(window.len())
+    }
+
+    fn encode_batches<E>(&self, batches: &[Vec<usize>], window: &Window, encoder: &E) -> Result<Vec<Vec<Vec<f32>>>>
+    where
+        E: DocumentEncoder + Sync,
+    {
+        /*
+        Let the workers pull batches from a shared counter until they are all encoded or one of them fails
+        */
+        let next = AtomicUsize::new(0);
+        let results: Mutex<Vec<Option<Vec<Vec<f32>>>>> = Mutex::new(vec![None; batches.len()]);
+        let failure: Mutex<Option<Error>> = Mutex::new(None);
+        let titles = window.titles();
+
+        std::thread::scope(|scope| {
+            for _ in 0..self.num_workers.min(batches.len()) {
+                scope.spawn(|| loop {
+                    let b = next.fetch_add(1, Ordering::SeqCst);
+                    if b >= batches.len() || failure.lock().map(|f| f.is_some()).unwrap_or(true) {
+                        break;
+                    }
+
+                    let texts: Vec<String> = batches[b].iter().map(|&i| window.texts[i].clone()).collect();
+                    let batch_titles: Option<Vec<String>> =
+                        titles.map(|titles| batches[b].iter().map(|&i| titles[i].clone()).collect());
+
+                    let encoded = encode_splitting(encoder, &texts, batch_titles.as_ref(), self.pooling, self.normalize);
+
+                    match encoded {
+                        Ok(embeddings) => {
+                            if let Ok(mut results) = results.lock() {
+                                results[b] = Some(embeddings);
+                            }
+                        }
+                        Err(err) => {
+                            if let Ok(mut failure) = failure.lock() {
+                                failure.get_or_insert(err);
+                            }
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        if let Some(err) = failure.into_inner().ok().flatten() {
+            return Err(err);
+        }
+
+        let results = results
+            .into_inner()
+            .map_err(|_| Error::Internal("An encoding worker panicked".to_string()))?;
+        results
+            .into_iter()
+            .map(|embeddings| embeddings.ok_or(Error::Internal("A batch was not encoded".to_string())))
+            .collect()
+    }
+}
+
+pub fn budget_batches(order: &[usize], lengths: &[usize], max_tokens: usize, max_batch_size: usize) -> Vec<Vec<usize>> {
+    /*
+    Group documents, taken in the given order, into batches whose padded size (documents * longest length)
+    stays within max_tokens, a document longer than the budget gets a batch of its own
+    */
+    let mut batches = Vec::new();
+    let mut batch: Vec<usize> = Vec::new();
+    let mut longest = 0;
+
+    for &i in order {
+        let candidate = usize::max(longest, lengths[i]);
+        if !batch.is_empty() && (candidate * (batch.len() + 1) > max_tokens || batch.len() >= max_batch_size) {
+            batches.push(std::mem::take(&mut batch));
+            longest = 0;
+        }
+        longest = usize::max(longest, lengths[i]);
+        batch.push(i);
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+
+    batches
+}
+
+pub fn encode_with_budget<E: DocumentEncoder>(
+    encoder: &E,
+    texts: &[String],
+    titles: Option<&Vec<String>>,
+    pooling: Pooling,
+    normalize: bool,
+    max_tokens: usize,
+) -> Result<Vec<Vec<f32>>> {
+    /*
+    Encode any number of documents in batches formed from their token lengths, returning the embeddings in input order
+    */
+    let lengths = encoder.token_lengths(texts, titles)?;
+    let mut order: Vec<usize> = (0..texts.len()).collect();
+    order.sort_by_key(|&i| lengths[i]);
+
+    let mut rows: Vec<Vec<f32>> = vec![Vec::new(); texts.len()];
+    for batch in budget_batches(&order, &lengths, max_tokens, usize::MAX) {
+        let batch_texts: Vec<String> = batch.iter().map(|&i| texts[i].clone()).collect();
+        let batch_titles: Option<Vec<String>> =
+            titles.map(|titles| batch.iter().map(|&i| titles[i].clone()).collect());
+
+        let embeddings = encode_splitting(encoder, &batch_texts, batch_titles.as_ref(), pooling, normalize)?;
+        for (&i, row) in batch.iter().zip(embeddings) {
+            rows[i] = row;
+        }
+    }
+
+    Ok(rows)
+}
+
+fn encode_splitting<E: DocumentEncoder + ?Sized>(
+    encoder: &E,
+    texts: &Vec<String>,
+    titles: Option<&Vec<String>>,
+    pooling: Pooling,
+    normalize: bool,
+) -> Result<Vec<Vec<f32>>> {
+    /*
+    Encode a batch, splitting it in halves and retrying whenever the forward pass runs out of device memory
+    This only helps on CUDA devices, see ModelSource::with_device, a failed allocation on the CPU aborts the process
+    instead of returning an error, so there the token budget is the only memory control
+    */
+    let encoded = encoder
+        .encode(texts, titles, pooling, normalize)
+        .and_then(|embeddings| Ok(embeddings.to_vec2::<f32>()?));
+
+    match encoded {
+        Err(err) if texts.len() > 1 && is_allocation_error(&err) => {
+            let middle = texts.len() / 2;
+            let (left_titles, right_titles) = match titles {
+                Some(titles) => (Some(titles[..middle].to_vec()), Some(titles[middle..].to_vec())),
+                None => (None, None),
+            };
+
+            let mut left = encode_splitting(encoder, &texts[..middle].to_vec(), left_titles.as_ref(), pooling, normalize)?;
+            let right = encode_splitting(encoder, &texts[middle..].to_vec(), right_titles.as_ref(), pooling, normalize)?;
+            left.extend(right);
+            Ok(left)
+        }
+        encoded => encoded,
+    }
+}
+
+fn is_allocation_error(err: &Error) -> bool {
+    /*
+    Recognise the out of memory errors candle's CUDA backend forwards from the driver and cuBLAS
+    */
+    let message = err.to_string();
+    ["CUDA_ERROR_OUT_OF_MEMORY", "CUBLAS_STATUS_ALLOC_FAILED", "out of memory"]
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
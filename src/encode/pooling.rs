@@ -0,0 +1,175 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use candle_core::{DType, Tensor, D};
+
+/// Pooling strategies for collapsing token-level hidden states into a single embedding
+/// It mirrors the `--pooling` options of Pyserini's encoders and sentence-transformers' Pooling module
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pooling {
+    /// Hidden state of the first ([CLS]) token
+    Cls,
+    /// Average of the hidden states of all non-padding tokens
+    Mean,
+    /// Element-wise maximum over the hidden states of all non-padding tokens
+    Max,
+    /// Hidden state of the last non-padding token, for both left and right padding
+    LastToken,
+    /// Position-weighted average of the hidden states of all non-padding tokens (SGPT)
+    WeightedMean,
+}
+
+impl FromStr for Pooling {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cls" => Ok(Pooling::Cls),
+            "mean" => Ok(Pooling::Mean),
+            "max" => Ok(Pooling::Max),
+            "last" | "lasttoken" | "last_token" => Ok(Pooling::LastToken),
+            "weightedmean" | "weighted_mean" => Ok(Pooling::WeightedMean),
+            _ => Err(Error::Config(format!(
+                "Unsupported pooling '{}', expected one of cls, mean, max, last_token or weighted_mean",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Pooling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Pooling::Cls => "cls",
+            Pooling::Mean => "mean",
+            Pooling::Max => "max",
+            Pooling::LastToken => "last_token",
+            Pooling::WeightedMean => "weighted_mean",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Pooling {
+    pub fn pool(&self, hidden_state: &Tensor, attention_mask: &Tensor, normalize: bool) -> Result<Tensor> {
+        /*
+        Pool a (batch, seq_len, hidden_size) tensor of hidden states into (batch, hidden_size),
+        using the (batch, seq_len) attention mask to ignore padding, and optionally L2-normalize the result
+        */
+        let (n_sentence, n_tokens, _hidden_size) = hidden_state.dims3()?;
+        if attention_mask.dims() != [n_sentence, n_tokens] {
+            return Err(Error::Config(format!(
+                "Attention mask of shape {:?} does not match hidden states of shape {:?}",
+                attention_mask.dims(),
+                hidden_state.dims()
+            )));
+        }
+
+        let embeddings = match self {
+            Pooling::Cls => cls_pooling(hidden_state)?,
+            Pooling::Mean => mean_pooling(hidden_state, attention_mask)?,
+            Pooling::Max => max_pooling(hidden_state, attention_mask)?,
+            Pooling::LastToken => last_token_pooling(hidden_state, attention_mask)?,
+            Pooling::WeightedMean => weighted_mean_pooling(hidden_state, attention_mask)?,
+        };
+
+        if normalize {
+            normalize_l2(&embeddings)
+        } else {
+            Ok(embeddings)
+        }
+    }
+}
+
+pub fn truncate_embeddings(embeddings: &Tensor, dimension: usize, normalize: bool) -> Result<Tensor> {
+    /*
+    Keep the first dimensions of (batch, hidden_size) Matryoshka embeddings, re-normalizing the prefixes if requested
+    */
+    let (_batch_size, hidden_size) = embeddings.dims2()?;
+    if dimension == 0 || dimension > hidden_size {
+        return Err(Error::DimensionMismatch {
+            expected: hidden_size,
+            actual: dimension,
+        });
+    }
+
+    let embeddings = embeddings.narrow(1, 0, dimension)?;
+    if normalize {
+        normalize_l2(&embeddings)
+    } else {
+        Ok(embeddings)
+    }
+}
+
+pub fn normalize_l2(v: &Tensor) -> Result<Tensor> {
+    Ok(v.broadcast_div(&v.sqr()?.sum_keepdim(1)?.sqrt()?)?)
+}
+
+pub fn cls_pooling(last_hidden_state: &Tensor) -> Result<Tensor> {
+    /*
+    Take the hidden state of the first token of every sentence
+    */
+    Ok(last_hidden_state.narrow(1, 0, 1)?.squeeze(1)?)
+}
+
+pub fn mean_pooling(last_hidden_state: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+    /*
+    Compute mean pooling of BERT hidden states, only counting the non-padding tokens
+    */
+    let mask = attention_mask.to_dtype(last_hidden_state.dtype())?.unsqueeze(2)?;
+    let summed = last_hidden_state.broadcast_mul(&mask)?.sum(1)?;
+    let n_tokens = mask.sum(1)?.clamp(1e-9, f64::MAX)?;
+
+    Ok(summed.broadcast_div(&n_tokens)?)
+}
+
+pub fn max_pooling(last_hidden_state: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+    /*
+    Compute max pooling of BERT hidden states, padding tokens are pushed to a large negative value first
+    */
+    let mask = attention_mask.to_dtype(last_hidden_state.dtype())?.unsqueeze(2)?;
+    let padding_penalty = ((mask - 1.0)? * 1e9)?;
+
+    Ok(last_hidden_state.broadcast_add(&padding_penalty)?.max(1)?)
+}
+
+pub fn last_token_pooling(last_hidden_state: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+    /*
+    Take the hidden state of the last non-padding token of every sentence
+    */
+    let attention_mask = attention_mask.to_dtype(DType::U32)?.to_vec2::<u32>()?;
+
+    let embeddings = attention_mask
+        .iter()
+        .enumerate()
+        .map(|(i, mask)| {
+            let last_token = mask
+                .iter()
+                .rposition(|&m| m == 1)
+                .ok_or(Error::InvalidInput(format!("Sentence {} has no tokens to pool", i)))?;
+            Ok(last_hidden_state.get(i)?.get(last_token)?)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Tensor::stack(&embeddings, 0)?)
+}
+
+pub fn weighted_mean_pooling(last_hidden_state: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+    /*
+    Compute a position-weighted mean of the hidden states, later tokens get linearly larger weights
+    */
+    let (_n_sentence, n_tokens, _hidden_size) = last_hidden_state.dims3()?;
+    let positions = Tensor::arange(1u32, n_tokens as u32 + 1, last_hidden_state.device())?
+        .to_dtype(last_hidden_state.dtype())?
+        .unsqueeze(0)?;
+    let weights = attention_mask
+        .to_dtype(last_hidden_state.dtype())?
+        .broadcast_mul(&positions)?
+        .unsqueeze(D::Minus1)?;
+
+    let summed = last_hidden_state.broadcast_mul(&weights)?.sum(1)?;
+    let total_weight = weights.sum(1)?.clamp(1e-9, f64::MAX)?;
+
+    Ok(summed.broadcast_div(&total_weight)?)
+}
@@ -0,0 +1,233 @@
+use candle_core::quantized::QMatMul;
+use candle_core::{DType, Module, Tensor};
+use candle_nn::{Embedding, LayerNorm};
+use candle_transformers::models::bert::{Config, HiddenAct};
+use candle_transformers::quantized_var_builder::VarBuilder;
+
+/// A BERT encoder whose linear layers run on quantized (GGUF) or f16 weights
+/// Embeddings and layer norms are kept in f32, as are the hidden states and the attention bias, which
+/// avoids the f16 overflow of candle's BertModel attention mask
+/// It follows the layout of candle_transformers::models::bert::BertModel so that GGUF files quantized
+/// from a Hugging Face BERT checkpoint load under their original tensor names
+pub struct QuantizedBertModel {
+    embeddings: QuantizedBertEmbeddings,
+    layers: Vec<QuantizedBertLayer>,
+}
+
+struct QuantizedBertEmbeddings {
+    word_embeddings: Embedding,
+    position_embeddings: Embedding,
+    token_type_embeddings: Embedding,
+    layer_norm: LayerNorm,
+}
+
+struct QuantizedBertLayer {
+    query: Linear,
+    key: Linear,
+    value: Linear,
+    attention_output: Linear,
+    attention_layer_norm: LayerNorm,
+    intermediate: Linear,
+    output: Linear,
+    output_layer_norm: LayerNorm,
+    num_attention_heads: usize,
+    hidden_act: HiddenAct,
+}
+
+/// A linear layer over quantized or f16 weights, taking and returning f32 activations
+struct Linear {
+    weight: QMatMul,
+    bias: Tensor,
+}
+
+impl Module for Linear {
+    fn forward(&self, xs: &Tensor) -> candle_core::Result<Tensor> {
+        self.weight.forward(xs)?.broadcast_add(&self.bias)
+    }
+}
+
+/// The weights a QuantizedBertModel is loaded from
+#[derive(Clone)]
+enum Weights {
+    // GGUF tensors, linear layers stay quantized
+    Quantized(VarBuilder),
+    // f32 safetensors or pytorch weights, linear layers are cast to f16
+    Half(candle_nn::VarBuilder<'static>),
+}
+
+impl Weights {
+    fn pp(&self, prefix: &str) -> Self {
+        match self {
+            Weights::Quantized(vb) => Weights::Quantized(vb.pp(prefix)),
+            Weights::Half(vb) => Weights::Half(vb.pp(prefix)),
+        }
+    }
+
+    fn contains_key(&self, name: &str) -> bool {
+        match self {
+            Weights::Quantized(vb) => vb.contains_key(name),
+            Weights::Half(vb) => vb.contains_tensor(name),
+        }
+    }
+
+    fn tensor(&self, shape: (usize, usize), name: &str) -> candle_core::Result<Tensor> {
+        match self {
+            Weights::Quantized(vb) => vb.get(shape, name)?.dequantize(vb.device()),
+            Weights::Half(vb) => vb.get(shape, name),
+        }
+    }
+
+    fn vector(&self, size: usize, name: &str) -> candle_core::Result<Tensor> {
+        match self {
+            Weights::Quantized(vb) => vb.get(size, name)?.dequantize(vb.device()),
+            Weights::Half(vb) => vb.get(size, name),
+        }
+    }
+
+    fn embedding(&self, vocab_size: usize, hidden_size: usize) -> candle_core::Result<Embedding> {
+        Ok(Embedding::new(self.tensor((vocab_size, hidden_size), "weight")?, hidden_size))
+    }
+
+    fn linear(&self, in_dim: usize, out_dim: usize) -> candle_core::Result<Linear> {
+        let weight = match self {
+            Weights::Quantized(vb) => QMatMul::from_arc(vb.get((out_dim, in_dim), "weight")?)?,
+            Weights::Half(vb) => QMatMul::TensorF16(vb.get((out_dim, in_dim), "weight")?.to_dtype(DType::F16)?),
+        };
+        Ok(Linear { weight, bias: self.vector(out_dim, "bias")? })
+    }
+
+    fn layer_norm(&self, size: usize, eps: f64) -> candle_core::Result<LayerNorm> {
+        Ok(LayerNorm::new(self.vector(size, "weight")?, self.vector(size, "bias")?, eps))
+    }
+}
+
+impl QuantizedBertEmbeddings {
+    fn load(vb: Weights, config: &Config) -> candle_core::Result<Self> {
+        let hidden_size = config.hidden_size;
+        Ok(Self {
+            word_embeddings: vb.pp("word_embeddings").embedding(config.vocab_size, hidden_size)?,
+            position_embeddings: vb.pp("position_embeddings").embedding(config.max_position_embeddings, hidden_size)?,
+            token_type_embeddings: vb.pp("token_type_embeddings").embedding(config.type_vocab_size, hidden_size)?,
+            layer_norm: vb.pp("LayerNorm").layer_norm(hidden_size, config.layer_norm_eps)?,
+        })
+    }
+
+    fn forward(&self, token_ids: &Tensor, token_type_ids: &Tensor) -> candle_core::Result<Tensor> {
+        let (_batch_size, seq_len) = token_ids.dims2()?;
+        let position_ids = Tensor::arange(0u32, seq_len as u32, token_ids.device())?;
+
+        let embeddings = self
+            .word_embeddings
+            .forward(token_ids)?
+            .add(&self.token_type_embeddings.forward(token_type_ids)?)?
+            .broadcast_add(&self.position_embeddings.forward(&position_ids)?)?;
+
+        self.layer_norm.forward(&embeddings)
+    }
+}
+
+impl QuantizedBertLayer {
+    fn load(vb: Weights, config: &Config) -> candle_core::Result<Self> {
+        let hidden_size = config.hidden_size;
+        let attention = vb.pp("attention");
+
+        Ok(Self {
+            query: attention.pp("self").pp("query").linear(hidden_size, hidden_size)?,
+            key: attention.pp("self").pp("key").linear(hidden_size, hidden_size)?,
+            value: attention.pp("self").pp("value").linear(hidden_size, hidden_size)?,
+            attention_output: attention.pp("output").pp("dense").linear(hidden_size, hidden_size)?,
+            attention_layer_norm: attention.pp("output").pp("LayerNorm").layer_norm(hidden_size, config.layer_norm_eps)?,
+            intermediate: vb.pp("intermediate").pp("dense").linear(hidden_size, config.intermediate_size)?,
+            output: vb.pp("output").pp("dense").linear(config.intermediate_size, hidden_size)?,
+            output_layer_norm: vb.pp("output").pp("LayerNorm").layer_norm(hidden_size, config.layer_norm_eps)?,
+            num_attention_heads: config.num_attention_heads,
+            hidden_act: config.hidden_act,
+        })
+    }
+
+    fn split_heads(&self, xs: &Tensor) -> candle_core::Result<Tensor> {
+        let (batch_size, seq_len, hidden_size) = xs.dims3()?;
+        xs.reshape((batch_size, seq_len, self.num_attention_heads, hidden_size / self.num_attention_heads))?
+            .transpose(1, 2)?
+            .contiguous()
+    }
+
+    fn forward(&self, hidden_states: &Tensor, attention_bias: &Tensor) -> candle_core::Result<Tensor> {
+        let (batch_size, seq_len, hidden_size) = hidden_states.dims3()?;
+        let head_size = hidden_size / self.num_attention_heads;
+
+        let query = self.split_heads(&self.query.forward(hidden_states)?)?;
+        let key = self.split_heads(&self.key.forward(hidden_states)?)?;
+        let value = self.split_heads(&self.value.forward(hidden_states)?)?;
+
+        let scores = (query.matmul(&key.t()?)? / (head_size as f64).sqrt())?;
+        let probabilities = candle_nn::ops::softmax_last_dim(&scores.broadcast_add(attention_bias)?)?;
+        let context = probabilities
+            .matmul(&value)?
+            .transpose(1, 2)?
+            .reshape((batch_size, seq_len, hidden_size))?;
+
+        let attention = self.attention_output.forward(&context)?;
+        let attention = self.attention_layer_norm.forward(&(attention + hidden_states)?)?;
+
+        let intermediate = self.intermediate.forward(&attention)?;
+        let intermediate = match self.hidden_act {
+            HiddenAct::Gelu => intermediate.gelu_erf()?,
+            HiddenAct::GeluApproximate => intermediate.gelu()?,
+            HiddenAct::Relu => intermediate.relu()?,
+        };
+        let output = self.output.forward(&intermediate)?;
+
+        self.output_layer_norm.forward(&(output + attention)?)
+    }
+}
+
+impl QuantizedBertModel {
+    pub fn load(vb: VarBuilder, config: &Config) -> candle_core::Result<Self> {
+        /*
+        Load the encoder from a GGUF file, with or without the `bert.` prefix of *ForMaskedLM exports
+        */
+        Self::load_weights(Weights::Quantized(vb), config)
+    }
+
+    pub fn load_f16(vb: candle_nn::VarBuilder<'static>, config: &Config) -> candle_core::Result<Self> {
+        /*
+        Load the encoder from f32 weights and run its linear layers in f16
+        */
+        Self::load_weights(Weights::Half(vb), config)
+    }
+
+    fn load_weights(vb: Weights, config: &Config) -> candle_core::Result<Self> {
+        let vb = if vb.contains_key("bert.embeddings.word_embeddings.weight") {
+            vb.pp("bert")
+        } else {
+            vb
+        };
+
+        let embeddings = QuantizedBertEmbeddings::load(vb.pp("embeddings"), config)?;
+        let layers = (0..config.num_hidden_layers)
+            .map(|i| QuantizedBertLayer::load(vb.pp(&format!("encoder.layer.{i}")), config))
+            .collect::<candle_core::Result<Vec<_>>>()?;
+
+        Ok(Self { embeddings, layers })
+    }
+
+    pub fn forward(&self, token_ids: &Tensor, token_type_ids: &Tensor, attention_mask: &Tensor) -> candle_core::Result<Tensor> {
+        /*
+        Return the (batch, seq_len, hidden_size) hidden states of a padded batch
+        */
+        // 0 on tokens, f32::MIN on padding, broadcast over heads and query positions
+        let attention_bias = attention_mask
+            .to_dtype(DType::F32)?
+            .affine(f32::MAX as f64, f32::MIN as f64)?
+            .unsqueeze(1)?
+            .unsqueeze(1)?;
+
+        let mut hidden_states = self.embeddings.forward(token_ids, token_type_ids)?;
+        for layer in &self.layers {
+            hidden_states = layer.forward(&hidden_states, &attention_bias)?;
+        }
+
+        Ok(hidden_states)
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::encode::auto::{Model, OutputModelType};
+use crate::error::{Error, Result};
+
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertForMaskedLM, BertModel, Config as BertConfig};
+use candle_transformers::models::distilbert::{
+    Config as DistilBertConfig, DistilBertForMaskedLM, DistilBertModel,
+};
+use candle_transformers::models::jina_bert::{BertModel as JinaBertModel, Config as JinaBertConfig};
+use candle_transformers::models::modernbert::{Config as ModernBertConfig, ModernBert};
+use candle_transformers::models::t5::{Config as T5Config, T5EncoderModel};
+use candle_transformers::models::xlm_roberta::{Config as XLMRobertaConfig, XLMRobertaModel};
+use serde_json::Value;
+
+/// A ModelLoader builds a `Model` from a VarBuilder over the checkpoint weights and the raw `config.json`
+pub type ModelLoader = fn(VarBuilder, &str, OutputModelType) -> Result<Model>;
+
+/// ModelRegistry maps `architectures` / `model_type` entries of a `config.json` to a ModelLoader
+/// The default registry knows every backbone rustserini supports, more can be added with `register`
+pub struct ModelRegistry {
+    loaders: HashMap<String, ModelLoader>,
+}
+
+impl Default for ModelRegistry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+
+        for name in ["BertModel", "BertForMaskedLM", "DPRContextEncoder", "DPRQuestionEncoder", "bert", "dpr"] {
+            registry.register(name, load_bert);
+        }
+        for name in ["DistilBertModel", "DistilBertForMaskedLM", "distilbert"] {
+            registry.register(name, load_distilbert);
+        }
+        for name in ["XLMRobertaModel", "XLMRobertaForMaskedLM", "xlm-roberta"] {
+            registry.register(name, load_xlm_roberta);
+        }
+        for name in ["ModernBertModel", "ModernBertForMaskedLM", "modernbert"] {
+            registry.register(name, load_modernbert);
+        }
+        for name in ["JinaBertModel", "JinaBertForMaskedLM"] {
+            registry.register(name, load_jina_bert);
+        }
+        for name in ["T5EncoderModel", "T5Model", "T5ForConditionalGeneration", "t5"] {
+            registry.register(name, load_t5_encoder);
+        }
+
+        registry
+    }
+}
+
+impl ModelRegistry {
+    pub fn new() -> Self {
+        /*
+        Create an empty registry, use `ModelRegistry::default()` for one with the built-in architectures
+         */
+        Self {
+            loaders: HashMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, name: impl Into<String>, loader: ModelLoader) {
+        /*
+        Register a loader under an architecture (e.g. "BertModel") or model_type (e.g. "bert") name
+         */
+        self.loaders.insert(name.into(), loader);
+    }
+
+    pub fn supported(&self) -> Vec<&str> {
+        /*
+        List the registered architecture and model_type names
+         */
+        let mut names: Vec<&str> = self.loaders.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    pub fn load(&self, vb: VarBuilder, config: &str, output_model_type: OutputModelType) -> Result<Model> {
+        /*
+        Load a model, looking the loader up by `architectures[0]` first and by `model_type` second
+         */
+        let model_configuration: Value = serde_json::from_str(config)?;
+        let architecture = model_configuration["architectures"][0].as_str();
+        let model_type = model_configuration["model_type"].as_str();
+
+        let loader = architecture
+            .and_then(|name| self.loaders.get(name))
+            .or_else(|| model_type.and_then(|name| self.loaders.get(name)))
+            .ok_or_else(|| {
+                Error::ModelLoad(format!(
+                    "Unsupported model architecture {:?} (model_type {:?}), supported architectures are: {}",
+                    architecture,
+                    model_type,
+                    self.supported().join(", ")
+                ))
+            })?;
+
+        loader(vb, config, output_model_type).map_err(|err| match err {
+            Error::ModelLoad(_) => err,
+            err => Error::ModelLoad(err.to_string()),
+        })
+    }
+}
+
+fn with_optional_prefix<'a>(vb: VarBuilder<'a>, probe: &str, prefix: &str) -> VarBuilder<'a> {
+    /*
+    Descend into `prefix` when the checkpoint stores the backbone under it (e.g. `roberta.` in *ForMaskedLM exports)
+     */
+    if !vb.contains_tensor(probe) && vb.contains_tensor(&format!("{}.{}", prefix, probe)) {
+        vb.pp(prefix)
+    } else {
+        vb
+    }
+}
+
+fn load_bert(vb: VarBuilder, config: &str, output_model_type: OutputModelType) -> Result<Model> {
+    let config: BertConfig = serde_json::from_str(config)?;
+
+    match output_model_type {
+        OutputModelType::BertModel => {
+            // Only the encoder is needed for dense representations, the MLM head is skipped
+            let model = BertModel::load(vb, &config)?;
+            Ok(Model::BertModel {model})
+        }
+        OutputModelType::BertForMaskedLM => {
+            if !vb.contains_tensor("cls.predictions.transform.dense.weight") {
+                return Err(Error::ModelLoad("BERT checkpoint does not provide a masked language modelling head".to_string()));
+            }
+            let model = BertForMaskedLM::load(vb, &config)?;
+            Ok(Model::BertForMaskedLM {model})
+        }
+    }
+}
+
+fn load_distilbert(vb: VarBuilder, config: &str, output_model_type: OutputModelType) -> Result<Model> {
+    let config: DistilBertConfig = serde_json::from_str(config)?;
+
+    match output_model_type {
+        OutputModelType::BertModel => {
+            let model = DistilBertModel::load(vb, &config)?;
+            Ok(Model::DistilBertModel {model})
+        }
+        OutputModelType::BertForMaskedLM => {
+            if !vb.contains_tensor("vocab_projector.bias") {
+                return Err(Error::ModelLoad("DistilBERT checkpoint does not provide a masked language modelling head".to_string()));
+            }
+            let model = DistilBertForMaskedLM::load(vb, &config)?;
+            Ok(Model::DistilBertForMaskedLM {model})
+        }
+    }
+}
+
+fn load_xlm_roberta(vb: VarBuilder, config: &str, output_model_type: OutputModelType) -> Result<Model> {
+    if let OutputModelType::BertForMaskedLM = output_model_type {
+        return Err(Error::ModelLoad("XLM-RoBERTa checkpoints are only supported as encoders".to_string()));
+    }
+
+    let config: XLMRobertaConfig = serde_json::from_str(config)?;
+    let vb = with_optional_prefix(vb, "embeddings.word_embeddings.weight", "roberta");
+    let model = XLMRobertaModel::new(&config, vb)?;
+
+    Ok(Model::XLMRobertaModel {model})
+}
+
+fn load_modernbert(vb: VarBuilder, config: &str, output_model_type: OutputModelType) -> Result<Model> {
+    if let OutputModelType::BertForMaskedLM = output_model_type {
+        return Err(Error::ModelLoad("ModernBERT checkpoints are only supported as encoders".to_string()));
+    }
+
+    let config: ModernBertConfig = serde_json::from_str(config)?;
+    // ModernBert::load expects the `model.` prefix of ModernBertForMaskedLM exports
+    let vb = if vb.contains_tensor("model.embeddings.tok_embeddings.weight") {
+        vb
+    } else {
+        vb.rename_f(|name: &str| name.strip_prefix("model.").unwrap_or(name).to_string())
+    };
+    let model = ModernBert::load(vb, &config)?;
+
+    Ok(Model::ModernBertModel {model})
+}
+
+fn load_jina_bert(vb: VarBuilder, config: &str, output_model_type: OutputModelType) -> Result<Model> {
+    if let OutputModelType::BertForMaskedLM = output_model_type {
+        return Err(Error::ModelLoad("JinaBERT checkpoints are only supported as encoders".to_string()));
+    }
+
+    let config: JinaBertConfig = serde_json::from_str(config)?;
+    let vb = with_optional_prefix(vb, "embeddings.word_embeddings.weight", "bert");
+    let model = JinaBertModel::new(vb, &config)?;
+
+    Ok(Model::JinaBertModel {model})
+}
+
+fn load_t5_encoder(vb: VarBuilder, config: &str, output_model_type: OutputModelType) -> Result<Model> {
+    if let OutputModelType::BertForMaskedLM = output_model_type {
+        return Err(Error::ModelLoad("T5 checkpoints are only supported as encoders".to_string()));
+    }
+
+    let config: T5Config = serde_json::from_str(config)?;
+    let model = T5EncoderModel::load(vb, &config)?;
+
+    Ok(Model::T5EncoderModel {model: Mutex::new(model)})
+}
@@ -0,0 +1,267 @@
+use crate::encode::auto::{load_var_builder, OutputModelType};
+use crate::encode::base::DocumentEncoder;
+use crate::encode::config::EncoderConfig;
+use crate::encode::core::EncoderCore;
+use crate::encode::pooling::{normalize_l2, truncate_embeddings, Pooling};
+use crate::encode::source::{ModelSource, WeightFiles};
+use crate::error::{Error, Result};
+use crate::searcher::faiss::model::{QueryEncoder, QueryType};
+
+use candle_core::{DType, Device, Module, Tensor};
+use candle_nn::Linear;
+use serde::Deserialize;
+use std::path::Path;
+use tokenizers::Encoding;
+
+pub const MODULES_FILE: &str = "modules.json";
+
+/// One entry of modules.json, e.g. {"idx": 1, "name": "1", "path": "1_Pooling", "type": "sentence_transformers.models.Pooling"}
+#[derive(Clone, Debug, Deserialize)]
+pub struct ModuleEntry {
+    pub path: String,
+    #[serde(rename = "type")]
+    pub module_type: String,
+}
+
+/// The config.json of a Pooling module, whose enabled modes are concatenated in this order
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+struct PoolingModuleConfig {
+    pooling_mode_cls_token: bool,
+    pooling_mode_max_tokens: bool,
+    pooling_mode_mean_tokens: bool,
+    pooling_mode_mean_sqrt_len_tokens: bool,
+    pooling_mode_weightedmean_tokens: bool,
+    pooling_mode_lasttoken: bool,
+}
+
+/// The config.json of a Dense module
+#[derive(Clone, Debug, Deserialize)]
+struct DenseModuleConfig {
+    #[serde(default = "default_activation")]
+    activation_function: String,
+}
+
+fn default_activation() -> String {
+    "torch.nn.modules.activation.Tanh".to_string()
+}
+
+/// A Dense module: a linear projection followed by an activation
+struct DenseModule {
+    linear: Linear,
+    activation: String,
+}
+
+impl DenseModule {
+    fn load(source: &ModelSource, path: &str, device: &Device) -> Result<Self> {
+        let config: DenseModuleConfig =
+            serde_json::from_str(&std::fs::read_to_string(source.get(&format!("{}/config.json", path))?)?)?;
+
+        let weights = match source.get(&format!("{}/model.safetensors", path)) {
+            Ok(weights) => WeightFiles::SafeTensors(vec![weights]),
+            Err(_) => WeightFiles::PyTorch(vec![source.get(&format!("{}/pytorch_model.bin", path))?]),
+        };
+        let vb = load_var_builder(&weights, DType::F32, device)?.pp("linear");
+        let weight = vb.get_unchecked("weight")?;
+        let bias = if vb.contains_tensor("bias") { Some(vb.get_unchecked("bias")?) } else { None };
+
+        Ok(Self { linear: Linear::new(weight, bias), activation: config.activation_function })
+    }
+
+    fn forward(&self, embeddings: &Tensor) -> Result<Tensor> {
+        let embeddings = self.linear.forward(embeddings)?;
+        let embeddings = match self.activation.rsplit('.').next().unwrap_or_default() {
+            "Identity" => embeddings,
+            "Tanh" => embeddings.tanh()?,
+            "ReLU" => embeddings.relu()?,
+            "GELU" => embeddings.gelu_erf()?,
+            "Sigmoid" => candle_nn::ops::sigmoid(&embeddings)?,
+            activation => {
+                return Err(Error::Config(format!("Unsupported Dense activation function {}", activation)))
+            }
+        };
+        Ok(embeddings)
+    }
+}
+
+/// A SentenceTransformerEncoder encodes texts the way SentenceTransformer.encode does, following the
+/// Transformer -> Pooling -> Dense -> Normalize module stack of modules.json instead of a pooling given by hand
+/// It is designed to be a parallel of this Python Class
+/// https://github.com/UKPLab/sentence-transformers/blob/master/sentence_transformers/SentenceTransformer.py
+pub struct SentenceTransformerEncoder {
+    core: EncoderCore,
+    pooling: Vec<Pooling>,
+    dense: Vec<DenseModule>,
+    normalize: bool,
+}
+
+impl SentenceTransformerEncoder {
+    pub fn from_source(source: &ModelSource) -> Result<Self> {
+        /*
+        Build the module stack listed in modules.json, each module directory is resolved inside the same source
+        */
+        let modules: Vec<ModuleEntry> = serde_json::from_str(&std::fs::read_to_string(source.get(MODULES_FILE)?)?)?;
+
+        let mut core = None;
+        let mut pooling = Vec::new();
+        let mut dense = Vec::new();
+        let mut normalize = false;
+
+        for module in &modules {
+            match module.module_type.rsplit('.').next().unwrap_or_default() {
+                "Transformer" => core = Some(Self::load_transformer(source, &module.path)?),
+                "Pooling" => pooling = Self::load_pooling(source, &module.path)?,
+                "Dense" => dense.push(DenseModule::load(source, &module.path, &source.device)?),
+                "Normalize" => normalize = true,
+                module_type => {
+                    return Err(Error::ModelLoad(format!("Unsupported sentence-transformers module {}", module_type)))
+                }
+            }
+        }
+
+        let core = core.ok_or_else(|| Error::ModelLoad(format!("{} has no Transformer module", MODULES_FILE)))?;
+        if pooling.is_empty() {
+            return Err(Error::ModelLoad(format!("{} has no Pooling module", MODULES_FILE)));
+        }
+
+        Ok(Self { core, pooling, dense, normalize })
+    }
+
+    fn load_transformer(source: &ModelSource, path: &str) -> Result<EncoderCore> {
+        /*
+        Load the backbone, capped at the max_seq_length of sentence_bert_config.json
+        Backbones stored in a sub directory are only supported for local checkpoints
+        */
+        let source = match path {
+            "" => source.clone(),
+            path if source.is_local() => ModelSource {
+                model_name_or_path: Path::new(&source.model_name_or_path).join(path).display().to_string(),
+                ..source.clone()
+            },
+            path => {
+                return Err(Error::ModelLoad(format!(
+                    "The Transformer module of {} lives in {}, download the repository to load it locally",
+                    source.model_name_or_path, path
+                )))
+            }
+        };
+
+        let core = EncoderCore::from_sources(&source, &source, OutputModelType::BertModel)?;
+        match source.get("sentence_bert_config.json") {
+            Ok(filename) => {
+                let config: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(filename)?)?;
+                match config["max_seq_length"].as_u64() {
+                    Some(max_length) => core.with_config(EncoderConfig::new(max_length as usize)),
+                    None => Ok(core),
+                }
+            }
+            Err(_) => Ok(core),
+        }
+    }
+
+    fn load_pooling(source: &ModelSource, path: &str) -> Result<Vec<Pooling>> {
+        let config: PoolingModuleConfig =
+            serde_json::from_str(&std::fs::read_to_string(source.get(&format!("{}/config.json", path))?)?)?;
+
+        if config.pooling_mode_mean_sqrt_len_tokens {
+            return Err(Error::Config("The mean_sqrt_len_tokens pooling mode is not supported".to_string()));
+        }
+        let modes = [
+            (config.pooling_mode_cls_token, Pooling::Cls),
+            (config.pooling_mode_max_tokens, Pooling::Max),
+            (config.pooling_mode_mean_tokens, Pooling::Mean),
+            (config.pooling_mode_weightedmean_tokens, Pooling::WeightedMean),
+            (config.pooling_mode_lasttoken, Pooling::LastToken),
+        ];
+
+        Ok(modes.iter().filter(|(enabled, _)| *enabled).map(|(_, pooling)| *pooling).collect())
+    }
+
+    pub fn with_config(mut self, config: EncoderConfig) -> Result<Self> {
+        /*
+        Set the maximum lengths, truncation and templates used when tokenizing, defaults to the max_seq_length of the model
+        */
+        self.core = self.core.with_config(config)?;
+        Ok(self)
+    }
+
+    pub fn pooling(&self) -> &[Pooling] {
+        &self.pooling
+    }
+
+    pub fn normalizes(&self) -> bool {
+        self.normalize
+    }
+
+    fn embed(&self, tokens: &[Encoding], normalize: bool) -> Result<Tensor> {
+        /*
+        Run the module stack over a tokenized batch, normalizing when the stack ends with Normalize or when asked to
+        */
+        let (hidden_state, attention_mask) = self.core.forward(tokens)?;
+        let hidden_state = hidden_state.to_dtype(DType::F32)?;
+
+        let pooled = self
+            .pooling
+            .iter()
+            .map(|pooling| pooling.pool(&hidden_state, &attention_mask, false))
+            .collect::<Result<Vec<_>>>()?;
+        let mut embeddings = Tensor::cat(&pooled, 1)?;
+        for dense in &self.dense {
+            embeddings = dense.forward(&embeddings)?;
+        }
+
+        let normalize = normalize || self.normalize;
+        match self.core.config().truncate_dim {
+            Some(dimension) => truncate_embeddings(&embeddings, dimension, normalize),
+            None if normalize => normalize_l2(&embeddings),
+            None => Ok(embeddings),
+        }
+    }
+}
+
+impl DocumentEncoder for SentenceTransformerEncoder {
+    fn new(
+        model_name: &str,
+        revision: &str,
+    ) -> Result<SentenceTransformerEncoder> {
+        Self::from_source(&ModelSource::new(model_name, revision))
+    }
+
+    fn encode(
+        &self,
+        texts: &Vec<String>,
+        titles: Option<&Vec<String>>,
+        _pooling: Pooling,
+        normalize: bool,
+    ) -> Result<Tensor> {
+        /*
+        Encode a list of texts and/or titles, the pooling comes from the Pooling module of the model
+        */
+        let tokens = self.core.tokenize_documents(texts, titles)?;
+
+        self.embed(&tokens, normalize)
+    }
+
+    fn token_lengths(&self, texts: &[String], titles: Option<&Vec<String>>) -> Result<Vec<usize>> {
+        self.core.token_lengths(texts, titles)
+    }
+}
+
+impl QueryEncoder for SentenceTransformerEncoder {
+    fn new(
+        model_name: &str,
+        revision: &str,
+    ) -> Result<Self> {
+        Self::from_source(&ModelSource::new(model_name, revision))
+    }
+
+    fn encode(&self, queries: QueryType, _pooling: Pooling, normalize: bool) -> Result<Tensor> {
+        let texts = match queries {
+            QueryType::Query { query } => vec![query],
+            QueryType::Queries { query } => query,
+        };
+        let tokens = self.core.tokenize_queries(&texts)?;
+
+        self.embed(&tokens, normalize)
+    }
+}
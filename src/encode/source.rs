@@ -0,0 +1,281 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use candle_core::{DType, Device};
+use hf_hub::api::sync::ApiBuilder;
+use hf_hub::{Cache, Repo, RepoType};
+use serde_json::Value;
+
+/// The weight files of a checkpoint, possibly sharded
+#[derive(Clone, Debug)]
+pub enum WeightFiles {
+    SafeTensors(Vec<PathBuf>),
+    PyTorch(Vec<PathBuf>),
+    Gguf(PathBuf),
+    Onnx(PathBuf),
+}
+
+/// ModelSource describes where the files of a checkpoint (config.json, tokenizer.json, weights) live
+/// It is either a plain local directory, a Hugging Face hub repository, or a repository in a local
+/// Hugging Face cache. In offline mode the hub API is never contacted.
+/// It also carries how the weights are loaded: their floating point dtype, or an explicit weight
+/// file such as a quantized GGUF export, and the device the model runs on.
+#[derive(Clone, Debug)]
+pub struct ModelSource {
+    pub model_name_or_path: String,
+    pub revision: String,
+    pub offline: bool,
+    pub cache_dir: Option<PathBuf>,
+    pub dtype: DType,
+    pub weights_file: Option<String>,
+    pub device: Device,
+}
+
+impl ModelSource {
+    pub fn new(model_name_or_path: impl Into<String>, revision: impl Into<String>) -> Self {
+        /*
+        A hub repository (downloaded into the default cache) or, if the name is an existing directory, a local checkpoint
+         */
+        Self {
+            model_name_or_path: model_name_or_path.into(),
+            revision: revision.into(),
+            offline: false,
+            cache_dir: None,
+            dtype: DType::F32,
+            weights_file: None,
+            device: Device::Cpu,
+        }
+    }
+
+    pub fn local(path: impl AsRef<Path>) -> Self {
+        /*
+        A plain directory containing config.json, tokenizer.json and the weights
+         */
+        Self {
+            model_name_or_path: path.as_ref().display().to_string(),
+            revision: "main".to_string(),
+            offline: true,
+            cache_dir: None,
+            dtype: DType::F32,
+            weights_file: None,
+            device: Device::Cpu,
+        }
+    }
+
+    pub fn offline(mut self, offline: bool) -> Self {
+        /*
+        Only resolve files from the local cache, never contacting the hub
+         */
+        self.offline = offline;
+        self
+    }
+
+    pub fn with_cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        /*
+        Use an explicit Hugging Face cache root instead of the default one (HF_HOME or ~/.cache/huggingface/hub)
+         */
+        self.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    pub fn with_dtype(mut self, dtype: DType) -> Self {
+        /*
+        Load floating point weights as F32 (the default) or run BERT encoders with F16 linear layers
+        BF16 checkpoints load with any architecture, upcast to F32 since the CPU cannot multiply bf16 matrices
+         */
+        self.dtype = dtype;
+        self
+    }
+
+    pub fn with_weights_file(mut self, filename: impl Into<String>) -> Self {
+        /*
+        Load an explicit weight file instead of the standard ones, e.g. "model-q8_0.gguf" or "onnx/model_quantized.onnx"
+         */
+        self.weights_file = Some(filename.into());
+        self
+    }
+
+    pub fn with_device(mut self, device: Device) -> Self {
+        /*
+        Run the model on another device than the CPU, e.g. `parse_device("cuda:0")?` with the cuda feature enabled
+         */
+        self.device = device;
+        self
+    }
+
+    pub fn is_local(&self) -> bool {
+        Path::new(&self.model_name_or_path).is_dir()
+    }
+
+    fn cache(&self) -> Cache {
+        match &self.cache_dir {
+            Some(cache_dir) => Cache::new(cache_dir.clone()),
+            None => Cache::default(),
+        }
+    }
+
+    fn repo(&self) -> Repo {
+        Repo::with_revision(
+            self.model_name_or_path.clone(),
+            RepoType::Model,
+            self.revision.clone(),
+        )
+    }
+
+    pub fn get(&self, filename: &str) -> Result<PathBuf> {
+        /*
+        Resolve a file of the checkpoint to a local path, downloading it from the hub unless offline
+         */
+        if self.is_local() {
+            let path = Path::new(&self.model_name_or_path).join(filename);
+            if path.is_file() {
+                Ok(path)
+            } else {
+                Err(Error::ModelLoad(format!(
+                    "Missing {} in local model directory {}",
+                    filename,
+                    self.model_name_or_path
+                )))
+            }
+        } else if self.offline {
+            let cache = self.cache();
+            cache.repo(self.repo()).get(filename).ok_or(Error::ModelLoad(format!(
+                "Missing {} for {} (revision {}) in the Hugging Face cache at {}, offline mode does not download files",
+                filename,
+                self.model_name_or_path,
+                self.revision,
+                cache.path().display()
+            )))
+        } else {
+            let api = ApiBuilder::from_cache(self.cache()).build()?;
+            api.repo(self.repo()).get(filename).map_err(|err| {
+                Error::ModelLoad(format!(
+                    "Could not fetch {} for {} (revision {}): {}",
+                    filename,
+                    self.model_name_or_path,
+                    self.revision,
+                    err
+                ))
+            })
+        }
+    }
+
+    fn list_files(&self) -> Result<Option<Vec<String>>> {
+        /*
+        List the files of the checkpoint, or None when only the cache can be probed one file at a time
+         */
+        if self.is_local() {
+            let files = std::fs::read_dir(&self.model_name_or_path)?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect();
+            Ok(Some(files))
+        } else if self.offline {
+            Ok(None)
+        } else {
+            let api = ApiBuilder::from_cache(self.cache()).build()?;
+            let info = api.repo(self.repo()).info()?;
+            Ok(Some(info.siblings.into_iter().map(|sibling| sibling.rfilename).collect()))
+        }
+    }
+
+    fn find(&self, filename: &str, files: &Option<Vec<String>>) -> Result<Option<PathBuf>> {
+        /*
+        Resolve a file that may be missing, local files are probed directly since the listing only covers the top level
+         */
+        if self.is_local() {
+            let path = Path::new(&self.model_name_or_path).join(filename);
+            return Ok(path.is_file().then_some(path));
+        }
+        match files {
+            Some(files) if !files.iter().any(|file| file == filename) => Ok(None),
+            Some(_) => Ok(Some(self.get(filename)?)),
+            None => Ok(self.cache().repo(self.repo()).get(filename)),
+        }
+    }
+
+    fn get_shards(&self, index_filename: &PathBuf) -> Result<Vec<PathBuf>> {
+        /*
+        Resolve every shard listed in the `weight_map` of a sharded checkpoint index
+         */
+        let index: Value = serde_json::from_str(&std::fs::read_to_string(index_filename)?)?;
+        let weight_map = index["weight_map"]
+            .as_object()
+            .ok_or(Error::ModelLoad(format!("Missing weight_map in {}", index_filename.display())))?;
+
+        let mut shards: Vec<&str> = weight_map.values().filter_map(|shard| shard.as_str()).collect();
+        shards.sort();
+        shards.dedup();
+
+        shards.iter().map(|shard| self.get(shard)).collect()
+    }
+
+    pub fn weights(&self) -> Result<WeightFiles> {
+        /*
+        Resolve the weights of the checkpoint, preferring safetensors over PyTorch pickles and single files over shards
+         */
+        if let Some(filename) = &self.weights_file {
+            let weights = self.get(filename)?;
+            return match weights.extension().and_then(|extension| extension.to_str()) {
+                Some("gguf") => Ok(WeightFiles::Gguf(weights)),
+                Some("onnx") => Ok(WeightFiles::Onnx(weights)),
+                Some("safetensors") => Ok(WeightFiles::SafeTensors(vec![weights])),
+                Some("bin") | Some("pt") | Some("pth") => Ok(WeightFiles::PyTorch(vec![weights])),
+                _ => Err(Error::ModelLoad(format!(
+                    "Unsupported weights file {}, expected a .safetensors, .bin, .pt, .pth, .gguf or .onnx file",
+                    filename
+                ))),
+            };
+        }
+
+        let files = self.list_files()?;
+
+        if let Some(weights) = self.find("model.safetensors", &files)? {
+            return Ok(WeightFiles::SafeTensors(vec![weights]));
+        }
+        if let Some(index) = self.find("model.safetensors.index.json", &files)? {
+            return Ok(WeightFiles::SafeTensors(self.get_shards(&index)?));
+        }
+        if let Some(weights) = self.find("pytorch_model.bin", &files)? {
+            return Ok(WeightFiles::PyTorch(vec![weights]));
+        }
+        if let Some(index) = self.find("pytorch_model.bin.index.json", &files)? {
+            return Ok(WeightFiles::PyTorch(self.get_shards(&index)?));
+        }
+        let gguf = files
+            .iter()
+            .flatten()
+            .find(|filename| filename.ends_with(".gguf"));
+        if let Some(filename) = gguf {
+            return Ok(WeightFiles::Gguf(self.get(filename)?));
+        }
+        for filename in ["model.onnx", "onnx/model.onnx"] {
+            if let Some(weights) = self.find(filename, &files)? {
+                return Ok(WeightFiles::Onnx(weights));
+            }
+        }
+
+        Err(Error::ModelLoad(format!(
+            "Missing weights for {} (revision {}), expected one of model.safetensors, model.safetensors.index.json, pytorch_model.bin, pytorch_model.bin.index.json, a .gguf file or model.onnx",
+            self.model_name_or_path,
+            self.revision
+        )))
+    }
+}
+
+pub fn parse_device(name: &str) -> Result<Device> {
+    /*
+    Parse a device name as given on the command line: cpu, cuda or cuda:<ordinal>
+    */
+    match name.to_lowercase().as_str() {
+        "cpu" => Ok(Device::Cpu),
+        "cuda" => Ok(Device::new_cuda(0)?),
+        device => match device.strip_prefix("cuda:").and_then(|ordinal| ordinal.parse::<usize>().ok()) {
+            Some(ordinal) => Ok(Device::new_cuda(ordinal)?),
+            None => Err(Error::Config(format!(
+                "Unsupported device '{}', expected one of cpu, cuda or cuda:<ordinal>",
+                name
+            ))),
+        },
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use crate::encode::auto::{batch_tensors, Model, OutputModelType};
+use crate::encode::base::SparseDocumentEncoder;
+use crate::encode::config::EncoderConfig;
+use crate::encode::core::EncoderCore;
+use crate::encode::source::ModelSource;
+use crate::error::{Error, Result};
+
+use candle_core::{DType, Device, Tensor};
+use tokenizers::{Encoding, Tokenizer};
+
+/// A SpladeDocumentEncoder for encoding documents into SPLADE term weights
+/// It is designed to be a parallel of this Python Class
+/// https://github.com/castorini/pyserini/blob/45edec7e618db621339958c89fdff1d4a7a8cb90/pyserini/encode/_splade.py
+pub struct SpladeDocumentEncoder {
+    core: EncoderCore,
+}
+
+pub fn splade_max_pooling(logits: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+    /*
+    Compute SPLADE weights as the max over the sequence of log(1 + ReLU(logits)),
+    padded positions are zeroed out before taking the max
+    */
+    let weights = (logits.relu()? + 1.0)?.log()?;
+    let attention_mask = attention_mask.to_dtype(weights.dtype())?.unsqueeze(2)?;
+    let weights = weights.broadcast_mul(&attention_mask)?;
+
+    Ok(weights.max(1)?)
+}
+
+pub fn to_term_weights(weights: &Tensor, tokenizer: &Tokenizer) -> Result<Vec<HashMap<String, f32>>> {
+    /*
+    Convert a (batch, vocab_size) tensor of weights into term -> weight maps, dropping zero weights
+    */
+    let weights = weights.to_vec2::<f32>()?;
+
+    let term_weights = weights
+        .iter()
+        .map(|row| {
+            row.iter()
+                .enumerate()
+                .filter(|(_, weight)| **weight > 0.0)
+                .filter_map(|(token_id, weight)| {
+                    tokenizer
+                        .id_to_token(token_id as u32)
+                        .map(|token| (token, *weight))
+                })
+                .collect::<HashMap<String, f32>>()
+        })
+        .collect();
+
+    Ok(term_weights)
+}
+
+pub fn splade_encode(
+    model: &Model,
+    tokenizer: &Tokenizer,
+    device: &Device,
+    tokens: Vec<Encoding>,
+) -> Result<Vec<HashMap<String, f32>>> {
+    /*
+    Run the MLM head over a tokenized batch and return its SPLADE term weights
+    */
+    let (token_ids, token_type_ids, attention_mask) = batch_tensors(&tokens, device)?;
+
+    if !model.is_masked_lm() {
+        return Err(Error::Config("SPLADE encoding requires a masked language model".to_string()));
+    }
+    let logits: Tensor = model.forward(&token_ids, &token_type_ids, &attention_mask)?.to_dtype(DType::F32)?;
+
+    let weights = splade_max_pooling(&logits, &attention_mask)?;
+    to_term_weights(&weights, tokenizer)
+}
+
+impl SpladeDocumentEncoder {
+    pub fn from_source(source: &ModelSource) -> Result<Self> {
+        Self::from_sources(source, source)
+    }
+
+    pub fn from_sources(model_source: &ModelSource, tokenizer_source: &ModelSource) -> Result<Self> {
+        /*
+        The model is loaded with its masked language modelling head, whose logits become the term weights
+        */
+        let core = EncoderCore::from_sources(model_source, tokenizer_source, OutputModelType::BertForMaskedLM)?;
+        Ok(Self { core })
+    }
+
+    pub fn with_config(mut self, config: EncoderConfig) -> Result<Self> {
+        self.core = self.core.with_config(config)?;
+        Ok(self)
+    }
+}
+
+impl SparseDocumentEncoder for SpladeDocumentEncoder {
+    // instantiating a new SpladeDocumentEncoder instance
+    fn new(
+        model_name: &str,
+        revision: &str,
+    ) -> Result<SpladeDocumentEncoder> {
+        Self::from_source(&ModelSource::new(model_name, revision))
+    }
+
+    fn encode(
+        &self,
+        texts: &Vec<String>,
+        titles: Option<&Vec<String>>,
+    ) -> Result<Vec<HashMap<String, f32>>> {
+        /*
+        Encode a list of texts and/or titles into a list of term -> weight maps
+        */
+        let tokens = self.core.tokenize_documents(texts, titles)?;
+
+        self.core.term_weights(tokens)
+    }
+}
@@ -0,0 +1,318 @@
+use crate::encode::base::DocumentEncoder;
+use crate::encode::config::{EncoderConfig, FieldJoin};
+use crate::encode::pooling::{truncate_embeddings, Pooling};
+use crate::encode::source::ModelSource;
+use crate::error::{Error, Result};
+use crate::searcher::faiss::model::{QueryEncoder, QueryType};
+
+use candle_core::{DType, Device, Tensor};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use tokenizers::models::wordlevel::WordLevel;
+use tokenizers::normalizers::Lowercase;
+use tokenizers::pre_tokenizers::bert::BertPreTokenizer;
+use tokenizers::Tokenizer;
+
+const UNKNOWN_TOKEN: &str = "[UNK]";
+
+/// A StaticEmbeddingEncoder averages the rows of a static token embedding table, with no transformer forward pass
+/// The table is either a Model2Vec distillation (model.safetensors holding `embeddings` next to tokenizer.json)
+/// or a word2vec / GloVe text file, tokenized on whitespace and punctuation. Tokens can be weighted with SIF,
+/// a / (a + p(token)), to down-weight frequent tokens
+/// It is designed to be a parallel of this Python Class
+/// https://github.com/MinishLab/model2vec/blob/main/model2vec/model.py
+pub struct StaticEmbeddingEncoder {
+    tokenizer: Tokenizer,
+    embeddings: Vec<f32>,
+    weights: Vec<f32>,
+    dimension: usize,
+    max_length: usize,
+    normalize: bool,
+    unknown_token_id: Option<u32>,
+    config: EncoderConfig,
+}
+
+impl StaticEmbeddingEncoder {
+    pub fn from_source(source: &ModelSource) -> Result<Self> {
+        /*
+        Load a Model2Vec distillation, model.safetensors holding `embeddings` and tokenizer.json, normalized if its config.json says so
+        */
+        let tensors = candle_core::safetensors::load(source.get("model.safetensors")?, &Device::Cpu)?;
+        let embeddings = tensors
+            .get("embeddings")
+            .ok_or_else(|| Error::ModelLoad("Model2Vec checkpoint does not provide an embeddings tensor".to_string()))?
+            .to_dtype(DType::F32)?;
+        let (vocab_size, dimension) = embeddings.dims2()?;
+
+        let mut tokenizer = Tokenizer::from_file(source.get("tokenizer.json")?)
+            .map_err(|err| Error::Tokenizer(err.to_string()))?;
+        tokenizer.with_padding(None);
+        tokenizer
+            .with_truncation(None)
+            .map_err(|err| Error::Tokenizer(err.to_string()))?;
+
+        let normalize = match source.get("config.json") {
+            Ok(filename) => {
+                let config: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(filename)?)?;
+                config["normalize"].as_bool().unwrap_or(false)
+            }
+            Err(_) => false,
+        };
+        let unknown_token_id = tokenizer.token_to_id(UNKNOWN_TOKEN);
+
+        Ok(Self {
+            tokenizer,
+            embeddings: embeddings.flatten_all()?.to_vec1::<f32>()?,
+            weights: vec![1.0; vocab_size],
+            dimension,
+            max_length: 512,
+            normalize,
+            unknown_token_id,
+            config: EncoderConfig::default(),
+        })
+    }
+
+    pub fn from_word_vectors(path: impl AsRef<Path>, lowercase: bool) -> Result<Self> {
+        /*
+        Load word2vec or GloVe vectors in text format, one "word v1 v2 ..." line per word, with or without the
+        "count dimension" header of word2vec. Lowercase the input for uncased vectors such as GloVe
+        */
+        let path = path.as_ref();
+        let reader = BufReader::new(std::fs::File::open(path)?);
+
+        let mut vocab: HashMap<String, u32> = HashMap::new();
+        let mut embeddings = Vec::new();
+        let mut dimension = 0;
+
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+            let word = match fields.next() {
+                Some(word) => word.to_string(),
+                None => continue,
+            };
+            let vector = fields
+                .map(|value| value.parse::<f32>())
+                .collect::<std::result::Result<Vec<f32>, _>>()
+                .map_err(|err| Error::CorpusParse(format!("{:?} line {}: {}", path, line_number + 1, err)))?;
+
+            if line_number == 0 && vector.len() == 1 && word.parse::<usize>().is_ok() {
+                continue;
+            }
+            if dimension == 0 {
+                dimension = vector.len();
+            } else if vector.len() != dimension {
+                return Err(Error::DimensionMismatch { expected: dimension, actual: vector.len() });
+            }
+            if vocab.contains_key(&word) {
+                continue;
+            }
+
+            vocab.insert(word, vocab.len() as u32);
+            embeddings.extend(vector);
+        }
+
+        if !vocab.contains_key(UNKNOWN_TOKEN) {
+            vocab.insert(UNKNOWN_TOKEN.to_string(), vocab.len() as u32);
+            embeddings.extend(vec![0.0; dimension]);
+        }
+        let vocab_size = vocab.len();
+        let unknown_token_id = vocab.get(UNKNOWN_TOKEN).copied();
+
+        let model = WordLevel::builder()
+            .vocab(vocab)
+            .unk_token(UNKNOWN_TOKEN.to_string())
+            .build()
+            .map_err(|err| Error::Tokenizer(err.to_string()))?;
+        let mut tokenizer = Tokenizer::new(model);
+        tokenizer.with_pre_tokenizer(Some(BertPreTokenizer));
+        if lowercase {
+            tokenizer.with_normalizer(Some(Lowercase));
+        }
+
+        Ok(Self {
+            tokenizer,
+            embeddings,
+            weights: vec![1.0; vocab_size],
+            dimension,
+            max_length: 512,
+            normalize: false,
+            unknown_token_id,
+            config: EncoderConfig::default(),
+        })
+    }
+
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        /*
+        Set how many tokens of each text are averaged, defaults to 512
+        */
+        self.max_length = max_length;
+        self
+    }
+
+    pub fn with_config(mut self, config: EncoderConfig) -> Result<Self> {
+        /*
+        Join fields, fill templates and truncate embeddings as the transformer encoders do, max_length included
+        Token averages have no segments, so the pair field join is rejected
+        */
+        config.validate()?;
+        if config.field_join == FieldJoin::Pair {
+            return Err(Error::Config(
+                "A static embedding encoder has no sentence pairs, join titles with space or separator:<string>"
+                    .to_string(),
+            ));
+        }
+        self.max_length = config.max_length;
+        self.config = config;
+        Ok(self)
+    }
+
+    pub fn with_sif(mut self, a: f32) -> Self {
+        /*
+        Weight tokens with SIF, estimating p(token) with Zipf's law from the row order of the table,
+        which is sorted by decreasing frequency in word2vec and GloVe files
+        Model2Vec distillations already apply this weighting to their embeddings
+        */
+        let harmonic: f32 = (1..=self.weights.len()).map(|rank| 1.0 / rank as f32).sum();
+        for (rank, weight) in self.weights.iter_mut().enumerate() {
+            let probability = 1.0 / ((rank + 1) as f32 * harmonic);
+            *weight = a / (a + probability);
+        }
+        self
+    }
+
+    pub fn with_sif_frequencies(mut self, a: f32, frequencies: &HashMap<String, u64>) -> Self {
+        /*
+        Weight tokens with SIF from corpus counts, tokens missing from the counts are treated as the rarest
+        */
+        let total = frequencies.values().sum::<u64>().max(1) as f32;
+        for (token, id) in self.tokenizer.get_vocab(true) {
+            let count = frequencies.get(&token).copied().unwrap_or(0);
+            if let Some(weight) = self.weights.get_mut(id as usize) {
+                *weight = a / (a + count as f32 / total);
+            }
+        }
+        self
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn tokenize(&self, texts: Vec<String>) -> Result<Vec<Vec<u32>>> {
+        /*
+        Tokenize without special tokens, dropping unknown tokens and keeping at most max_length tokens
+        */
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts, false)
+            .map_err(|err| Error::Tokenizer(err.to_string()))?;
+
+        Ok(encodings
+            .iter()
+            .map(|encoding| {
+                encoding
+                    .get_ids()
+                    .iter()
+                    .copied()
+                    .filter(|id| Some(*id) != self.unknown_token_id && (*id as usize) < self.weights.len())
+                    .take(self.max_length)
+                    .collect()
+            })
+            .collect())
+    }
+
+    fn document_texts(&self, texts: &[String], titles: Option<&Vec<String>>) -> Result<Vec<String>> {
+        self.config.join_fields(&self.tokenizer, texts, titles)
+    }
+
+    fn query_texts(&self, queries: Vec<String>) -> Result<Vec<String>> {
+        queries
+            .iter()
+            .map(|query| {
+                let query = self.config.truncate_field(&self.tokenizer, query, self.config.max_text_length)?;
+                Ok(self.config.format_query(&query))
+            })
+            .collect()
+    }
+
+    fn embed(&self, texts: Vec<String>, normalize: bool) -> Result<Tensor> {
+        /*
+        Weighted mean of the token embeddings of each text, texts without known tokens get a zero vector
+        */
+        let ids = self.tokenize(texts)?;
+        let mut values = vec![0.0; ids.len() * self.dimension];
+
+        for (embedding, ids) in values.chunks_exact_mut(self.dimension.max(1)).zip(&ids) {
+            let mut total_weight = 0.0;
+            for id in ids {
+                let weight = self.weights[*id as usize];
+                let row = &self.embeddings[*id as usize * self.dimension..(*id as usize + 1) * self.dimension];
+                for (value, row_value) in embedding.iter_mut().zip(row) {
+                    *value += weight * row_value;
+                }
+                total_weight += weight;
+            }
+            if total_weight > 0.0 {
+                embedding.iter_mut().for_each(|value| *value /= total_weight);
+            }
+        }
+
+        let embeddings = Tensor::from_vec(values, (ids.len(), self.dimension), &Device::Cpu)?;
+        if let Some(dimension) = self.config.truncate_dim {
+            return truncate_embeddings(&embeddings, dimension, normalize || self.normalize);
+        }
+        if normalize || self.normalize {
+            // Zero vectors stay zero instead of turning into NaN
+            let norms = embeddings.sqr()?.sum_keepdim(1)?.sqrt()?.clamp(1e-12, f32::MAX)?;
+            Ok(embeddings.broadcast_div(&norms)?)
+        } else {
+            Ok(embeddings)
+        }
+    }
+}
+
+impl DocumentEncoder for StaticEmbeddingEncoder {
+    fn new(
+        model_name: &str,
+        revision: &str,
+    ) -> Result<StaticEmbeddingEncoder> {
+        Self::from_source(&ModelSource::new(model_name, revision))
+    }
+
+    fn encode(
+        &self,
+        texts: &Vec<String>,
+        titles: Option<&Vec<String>>,
+        _pooling: Pooling,
+        normalize: bool,
+    ) -> Result<Tensor> {
+        /*
+        Encode a list of texts and/or titles, tokens are always mean pooled
+        */
+        self.embed(self.document_texts(texts, titles)?, normalize)
+    }
+
+    fn token_lengths(&self, texts: &[String], titles: Option<&Vec<String>>) -> Result<Vec<usize>> {
+        Ok(self.tokenize(self.document_texts(texts, titles)?)?.iter().map(Vec::len).collect())
+    }
+}
+
+impl QueryEncoder for StaticEmbeddingEncoder {
+    fn new(
+        model_name: &str,
+        revision: &str,
+    ) -> Result<Self> {
+        Self::from_source(&ModelSource::new(model_name, revision))
+    }
+
+    fn encode(&self, queries: QueryType, _pooling: Pooling, normalize: bool) -> Result<Tensor> {
+        let texts = match queries {
+            QueryType::Query { query } => vec![query],
+            QueryType::Queries { query } => query,
+        };
+
+        self.embed(self.query_texts(texts)?, normalize)
+    }
+}
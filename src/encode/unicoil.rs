@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use crate::encode::auto::{load_var_builder, OutputModelType};
+use crate::encode::base::SparseDocumentEncoder;
+use crate::encode::config::EncoderConfig;
+use crate::encode::core::EncoderCore;
+use crate::encode::source::ModelSource;
+use crate::error::{Error, Result};
+
+use candle_core::{DType, Module, Tensor};
+use candle_nn::Linear;
+use tokenizers::{Encoding, Tokenizer};
+
+/// A UniCoilDocumentEncoder for encoding documents into one impact weight per wordpiece
+/// A linear head on top of BERT predicts the weight of every input token, the weight of a term is
+/// its highest weight in the document. TILDEv2 checkpoints share the same `tok_proj` head
+/// It is designed to be a parallel of this Python Class
+/// https://github.com/castorini/pyserini/blob/45edec7e618db621339958c89fdff1d4a7a8cb90/pyserini/encode/_unicoil.py
+pub struct UniCoilDocumentEncoder {
+    core: EncoderCore,
+    tok_proj: Linear,
+}
+
+pub fn to_impact_weights(tokens: &[Encoding], weights: &Tensor, tokenizer: &Tokenizer) -> Result<Vec<HashMap<String, f32>>> {
+    /*
+    Turn (batch, seq_len) token weights into term -> weight maps, keeping the highest weight of repeated
+    terms and skipping [CLS] and the padding
+    */
+    let weights = weights.to_vec2::<f32>()?;
+
+    let term_weights = tokens
+        .iter()
+        .zip(weights.iter())
+        .map(|(encoding, weights)| {
+            let mut term_weights: HashMap<String, f32> = HashMap::new();
+            let positions = encoding.get_ids().iter().zip(encoding.get_attention_mask()).zip(weights);
+
+            for ((&id, &mask), &weight) in positions {
+                let token = match tokenizer.id_to_token(id) {
+                    Some(token) if mask == 1 && token != "[CLS]" => token,
+                    _ => continue,
+                };
+                let entry = term_weights.entry(token).or_insert(weight);
+                *entry = entry.max(weight);
+            }
+            term_weights
+        })
+        .collect();
+
+    Ok(term_weights)
+}
+
+impl UniCoilDocumentEncoder {
+    pub fn from_source(source: &ModelSource) -> Result<Self> {
+        Self::from_sources(source, source)
+    }
+
+    pub fn from_sources(model_source: &ModelSource, tokenizer_source: &ModelSource) -> Result<Self> {
+        /*
+        The impact head is read from `tok_proj`, as in castorini/unicoil-msmarco-passage
+        */
+        Self::from_sources_with_head(model_source, tokenizer_source, "tok_proj")
+    }
+
+    pub fn from_sources_with_head(model_source: &ModelSource, tokenizer_source: &ModelSource, head: &str) -> Result<Self> {
+        /*
+        Create a UniCoilDocumentEncoder whose hidden_size -> 1 impact head is stored under another name than `tok_proj`
+        */
+        let core = EncoderCore::from_sources(model_source, tokenizer_source, OutputModelType::BertModel)?;
+
+        let vb = load_var_builder(&model_source.weights()?, DType::F32, core.device())?.pp(head);
+        let missing_head = |_| Error::ModelLoad(format!("Impact checkpoint does not provide a {} head", head));
+        let weight = vb.get_unchecked("weight").map_err(missing_head)?;
+        let bias = vb.get_unchecked("bias").map_err(missing_head)?;
+        let tok_proj = Linear::new(weight, Some(bias));
+
+        Ok(Self { core, tok_proj })
+    }
+
+    pub fn with_config(mut self, config: EncoderConfig) -> Result<Self> {
+        self.core = self.core.with_config(config)?;
+        Ok(self)
+    }
+}
+
+impl SparseDocumentEncoder for UniCoilDocumentEncoder {
+    // instantiating a new UniCoilDocumentEncoder instance
+    fn new(
+        model_name: &str,
+        revision: &str,
+    ) -> Result<UniCoilDocumentEncoder> {
+        Self::from_source(&ModelSource::new(model_name, revision))
+    }
+
+    fn encode(
+        &self,
+        texts: &Vec<String>,
+        titles: Option<&Vec<String>>,
+    ) -> Result<Vec<HashMap<String, f32>>> {
+        /*
+        Encode a list of texts and/or titles into a list of wordpiece -> impact weight maps
+        */
+        let tokens = self.core.tokenize_documents(texts, titles)?;
+        let (hidden_state, _attention_mask) = self.core.forward(&tokens)?;
+
+        let weights = self
+            .tok_proj
+            .forward(&hidden_state.to_dtype(DType::F32)?)?
+            .relu()?
+            .squeeze(2)?;
+
+        to_impact_weights(&tokens, &weights, self.core.tokenizer())
+    }
+}
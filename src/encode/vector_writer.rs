@@ -1,5 +1,6 @@
 use crate::encode::base::RepresentationWriter;
-use anyhow::Ok;
+use crate::encode::bge_m3::M3Embeddings;
+use crate::error::{Error, Result};
 use faiss::index::io::write_index;
 use faiss::index::IndexImpl;
 use faiss::{index_factory, Index, MetricType};
@@ -22,6 +23,16 @@ pub struct JsonlRepresentationWriter {
     pub dimension: u32,
 }
 
+fn json_to_string(value: &Value) -> String {
+    /*
+    Read a JSON string without its surrounding quotes and escapes, other values keep their JSON form
+    */
+    match value.as_str() {
+        Some(value) => value.to_string(),
+        None => value.to_string(),
+    }
+}
+
 /// JsonlCollectionIterator is a struct created for iterating over the items in a jsonl file
 /// It is designed to be a parallel of this Python Class
 /// https://github.com/castorini/pyserini/blob/45edec7e618db621339958c89fdff1d4a7a8cb90/pyserini/encode/_base.py#L59
@@ -51,6 +62,8 @@ pub struct AllInfo {
 }
 
 /// FaissRepresentationWriter is a struct that writes for writing embeddings to a faiss index
+/// Index types that need training, such as "PCA256,Flat" which learns a PCA reduction stored in the index
+/// and applied to the queries by faiss itself, are trained on the first `training_sample` embeddings
 pub struct FaissRepresentationWriter {
     pub dir_path: PathBuf,
     index_name: String,
@@ -59,6 +72,52 @@ pub struct FaissRepresentationWriter {
     pub index: IndexImpl,
     file: Option<std::fs::File>,
     pub docids: Vec<String>,
+    training_sample: usize,
+    untrained: Vec<f32>,
+}
+
+/// JsonVectorCollectionWriter writes term -> weight maps as an Anserini JsonVectorCollection
+/// Weights are multiplied by the quantization factor and rounded to integers, the collection can then be
+/// indexed with `lucene_indexer --collection JsonVectorCollection --impact --pretokenized`
+pub struct JsonVectorCollectionWriter {
+    dir_path: PathBuf,
+    filename: String,
+    file: Option<std::fs::File>,
+    pub quantization_factor: f32,
+}
+
+pub fn quantize_weights(weights: &HashMap<String, f32>, quantization_factor: f32) -> HashMap<String, u32> {
+    /*
+    Scale and round the weights into integer impacts, dropping the terms that round to zero
+    */
+    weights
+        .iter()
+        .map(|(term, weight)| (term.clone(), (weight * quantization_factor).round().max(0.0) as u32))
+        .filter(|(_, impact)| *impact > 0)
+        .collect()
+}
+
+/// ColBertIndexWriter stores the per-token vectors of multi-vector encoders
+/// The vectors of all documents are appended to a faiss index used for candidate generation and to a raw
+/// little-endian f32 `vectors` file used for exact MaxSim scoring, `doclens` holds the number of vectors of
+/// each document, one per line and in the order of `docid`, so the offset of a document is the sum of the previous lengths
+pub struct ColBertIndexWriter {
+    pub dir_path: PathBuf,
+    pub dimension: u32,
+    pub index: IndexImpl,
+    vectors: std::io::BufWriter<File>,
+    pub docids: Vec<String>,
+    pub doclens: Vec<usize>,
+}
+
+/// M3RepresentationWriter persists the dense, sparse and multi-vector outputs of a BgeM3Encoder in the same pass
+/// The dense embeddings go to any RepresentationWriter (Faiss or jsonl), the token weights to a JsonVectorCollection
+/// and the per-token vectors to a ColBertIndexWriter. Representations without a writer are skipped
+#[derive(Default)]
+pub struct M3RepresentationWriter {
+    pub dense: Option<Box<dyn RepresentationWriter>>,
+    pub sparse: Option<JsonVectorCollectionWriter>,
+    pub multi_vector: Option<ColBertIndexWriter>,
 }
 
 ///jsonl_collection_iterator is a struct created for iterating over the items in a jsonl file
@@ -93,7 +152,7 @@ impl JsonlCollectionIterator {
         }
     }
 
-    pub fn load(&mut self, collection_path: String) -> Result<(), anyhow::Error> {
+    pub fn load(&mut self, collection_path: String) -> Result<()> {
         /*
         This function loads an entire JSON collection or a folder of JSON files.
         */
@@ -119,30 +178,32 @@ impl JsonlCollectionIterator {
         for filename in filenames {
             println!("Loading file: {:?}", &filename);
 
-            let file = File::open(filename)?;
+            let file = File::open(&filename)?;
             let reader = BufReader::new(file);
             let lines = reader.lines();
 
-            for line in tqdm!(lines) {
+            for (line_number, line) in tqdm!(lines.enumerate()) {
                 let line = line?;
-                let json: Value = serde_json::from_str(&line)?;
+                let json: Value = serde_json::from_str(&line).map_err(|err| {
+                    Error::CorpusParse(format!("{:?} line {}: {}", filename, line_number + 1, err))
+                })?;
 
                 let docid = &json["id"];
-                all_doc_ids.push(docid.to_string());
+                all_doc_ids.push(json_to_string(docid));
 
                 for field in self.fields.to_vec() {
                     match field.as_str() {
                         "contents" => {
                             let value = &json["contents"];
-                            all_texts.push(value.to_string());
+                            all_texts.push(json_to_string(value));
                         }
                         "text" => {
                             let value = &json["text"];
-                            all_texts.push(value.to_string());
+                            all_texts.push(json_to_string(value));
                         }
                         "title" => {
                             let value = &json["title"];
-                            all_titles.push(value.to_string());
+                            all_titles.push(json_to_string(value));
                         }
                         _ => {}
                     }
@@ -157,7 +218,7 @@ impl JsonlCollectionIterator {
         Ok(())
     }
 
-    pub fn load_compressed(&mut self, collection_path: String) -> Result<(), anyhow::Error> {
+    pub fn load_compressed(&mut self, collection_path: String) -> Result<()> {
         /*
         This function loads a compressed JSON collection or a folder of JSON files.
         TODO: Merge this function with load()
@@ -184,15 +245,17 @@ impl JsonlCollectionIterator {
         for filename in filenames {
             println!("Loading file: {:?}", &filename);
 
-            let file = File::open(filename)?;
+            let file = File::open(&filename)?;
 
             let gz = GzDecoder::new(file);
             let reader = BufReader::new(gz);
             let lines = reader.lines();
 
-            for line in tqdm!(lines) {
+            for (line_number, line) in tqdm!(lines.enumerate()) {
                 let line = line?;
-                let json: DataFields = serde_json::from_str(&line)?;
+                let json: DataFields = serde_json::from_str(&line).map_err(|err| {
+                    Error::CorpusParse(format!("{:?} line {}: {}", filename, line_number + 1, err))
+                })?;
 
                 let docid = &json.docid;
                 all_doc_ids.push(docid.to_string());
@@ -263,6 +326,13 @@ impl JsonlCollectionIterator {
                 batch_info.insert("id", batch_docid);
                 batch_info.insert("text", batch_text);
 
+                // Titles are only present when the "title" field was requested
+                if self.all_info.titles.len() > idx {
+                    let end = usize::min(idx + self.batch_size, self.all_info.titles.len());
+                    let batch_title: Vec<String> = self.all_info.titles[idx..end].to_vec();
+                    batch_info.insert("title", batch_title);
+                }
+
                 batch_info
             })
     }
@@ -274,11 +344,11 @@ impl RepresentationWriter for JsonlRepresentationWriter {
         &mut self,
         batch_info: &HashMap<&str, Vec<String>>,
         embeddings: &mut Vec<f32>,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<()> {
         let mut file = match &self.file {
             Some(file) => file,
             None => {
-                panic!("File is not open for writing!");
+                return Err(Error::Io(std::io::Error::other("File is not open for writing!")));
             }
         };
 
@@ -294,28 +364,28 @@ impl RepresentationWriter for JsonlRepresentationWriter {
                 "contents": contents,
                 "vector": vector,
             });
-            let _ = writeln!(file, "{}", record);
+            writeln!(file, "{}", record)?;
         }
 
         Ok(())
     }
 
     // Create a new instance of a RepresentationWriter
-    fn new(path: &str, dimension: u32) -> JsonlRepresentationWriter {
+    fn new(path: &str, dimension: u32) -> Result<JsonlRepresentationWriter> {
         let dir_path = PathBuf::from(path);
         let filename = "embeddings.jsonl".to_string();
         let file = None;
 
-        JsonlRepresentationWriter {
+        Ok(JsonlRepresentationWriter {
             dir_path,
             filename,
             file,
             dimension,
-        }
+        })
     }
 
     // Open File
-    fn open_file(&mut self) -> Result<(), anyhow::Error> {
+    fn open_file(&mut self) -> Result<()> {
         if !self.dir_path.exists() {
             std::fs::create_dir_all(&self.dir_path)?;
         }
@@ -327,60 +397,91 @@ impl RepresentationWriter for JsonlRepresentationWriter {
         Ok(())
     }
 
-    fn save_index(&mut self) -> Result<(), anyhow::Error> {
-        panic!("Not implemented!");
+    // The embeddings are written to the jsonl file as they come, there is no separate index
+    fn save_index(&mut self) -> Result<()> {
+        Ok(())
     }
 
-    fn init_index(&mut self, _dim: u32, _index_type: &str) {
-        panic!("Not implemented!");
+    fn init_index(&mut self, dim: u32, _index_type: &str) -> Result<()> {
+        self.dimension = dim;
+        Ok(())
     }
 
-    fn save_docids(&mut self) -> Result<(), anyhow::Error> {
-        panic!("Not implemented!");
+    // The docids are stored alongside the embeddings in the jsonl file
+    fn save_docids(&mut self) -> Result<()> {
+        Ok(())
     }
 }
 
-impl Default for FaissRepresentationWriter {
-    fn default() -> Self {
-        Self {
-            dir_path: PathBuf::from("corpus"),
-            index_name: String::from("index"),
-            file_name: String::from("docid"),
-            dimension: 768,
-            index: index_factory(768, "Flat", MetricType::InnerProduct).unwrap(),
-            file: None,
-            docids: Vec::new(),
-        }
+impl FaissRepresentationWriter {
+    pub fn with_training_sample(mut self, training_sample: usize) -> Self {
+        /*
+        Set how many embeddings are used to train index types that need training, defaults to 65536
+        */
+        self.training_sample = training_sample.max(1);
+        self
+    }
+
+    fn train(&mut self) -> Result<()> {
+        /*
+        Train the index on the embeddings held back so far and add them to it
+        */
+        let sample = std::mem::take(&mut self.untrained);
+        self.index.train(&sample)?;
+        self.index.add(&sample)?;
+        Ok(())
     }
 }
 
 impl RepresentationWriter for FaissRepresentationWriter {
     // Create a new instance of a RepresentationWriter
-    fn new(path: &str, dimension: u32) -> Self {
+    fn new(path: &str, dimension: u32) -> Result<Self> {
         let dir_path = PathBuf::from(path);
         if !dir_path.exists() {
-            std::fs::create_dir_all(&dir_path).unwrap();
+            std::fs::create_dir_all(&dir_path)?;
         }
 
-        Self {
+        Ok(Self {
             dir_path,
+            index_name: String::from("index"),
+            file_name: String::from("docid"),
             dimension,
-            ..Default::default()
-        }
+            index: index_factory(dimension, "Flat", MetricType::InnerProduct)?,
+            file: None,
+            docids: Vec::new(),
+            training_sample: 65536,
+            untrained: Vec::new(),
+        })
     }
 
-    fn init_index(&mut self, dim: u32, index_type: &str) {
+    fn init_index(&mut self, dim: u32, index_type: &str) -> Result<()> {
         self.dimension = dim;
-        self.index = index_factory(dim, index_type, MetricType::InnerProduct).unwrap();
+        self.index = index_factory(dim, index_type, MetricType::InnerProduct)?;
+        Ok(())
     }
 
     fn write(
         &mut self,
         batch_info: &HashMap<&str, Vec<String>>,
         embeddings: &mut Vec<f32>,
-    ) -> Result<(), anyhow::Error> {
-        let embeddings = embeddings.as_mut_slice();
-        self.index.add(embeddings).unwrap();
+    ) -> Result<()> {
+        let expected = batch_info["id"].len() * self.dimension as usize;
+        if embeddings.len() != expected {
+            return Err(Error::DimensionMismatch {
+                expected,
+                actual: embeddings.len(),
+            });
+        }
+
+        if self.index.is_trained() {
+            self.index.add(embeddings.as_slice())?;
+        } else {
+            // Hold the embeddings back until there are enough of them to train the index
+            self.untrained.extend_from_slice(embeddings);
+            if self.untrained.len() >= self.training_sample * self.dimension as usize {
+                self.train()?;
+            }
+        }
 
         self.docids.extend(batch_info["id"].clone());
 
@@ -388,7 +489,7 @@ impl RepresentationWriter for FaissRepresentationWriter {
     }
 
     // Open File
-    fn open_file(&mut self) -> Result<(), anyhow::Error> {
+    fn open_file(&mut self) -> Result<()> {
         if !self.dir_path.exists() {
             std::fs::create_dir_all(&self.dir_path)?;
         }
@@ -400,18 +501,21 @@ impl RepresentationWriter for FaissRepresentationWriter {
         Ok(())
     }
 
-    fn save_index(&mut self) -> Result<(), anyhow::Error> {
+    fn save_index(&mut self) -> Result<()> {
+        if !self.untrained.is_empty() {
+            self.train()?;
+        }
         let index_file_path: PathBuf = self.dir_path.join(&self.index_name);
-        write_index(&self.index, index_file_path.as_path().display().to_string()).unwrap();
+        write_index(&self.index, index_file_path.as_path().display().to_string())?;
 
         Ok(())
     }
 
-    fn save_docids(&mut self) -> Result<(), anyhow::Error> {
+    fn save_docids(&mut self) -> Result<()> {
         let mut file = match &self.file {
             Some(file) => file,
             None => {
-                panic!("File is not open for writing!");
+                return Err(Error::Io(std::io::Error::other("File is not open for writing!")));
             }
         };
 
@@ -422,3 +526,198 @@ impl RepresentationWriter for FaissRepresentationWriter {
         Ok(())
     }
 }
+
+impl ColBertIndexWriter {
+    pub fn new(path: &str, dimension: u32) -> Result<Self> {
+        /*
+        Create the index directory and its vectors file, with a flat inner product index for candidate generation
+        */
+        let dir_path = PathBuf::from(path);
+        if !dir_path.exists() {
+            std::fs::create_dir_all(&dir_path)?;
+        }
+        let vectors = std::io::BufWriter::new(File::create(dir_path.join("vectors"))?);
+
+        Ok(Self {
+            dir_path,
+            dimension,
+            index: index_factory(dimension, "Flat", MetricType::InnerProduct)?,
+            vectors,
+            docids: Vec::new(),
+            doclens: Vec::new(),
+        })
+    }
+
+    pub fn init_index(&mut self, index_type: &str) -> Result<()> {
+        /*
+        Replace the candidate generation index with any faiss index_factory description that needs no training
+        */
+        self.index = index_factory(self.dimension, index_type, MetricType::InnerProduct)?;
+        Ok(())
+    }
+
+    pub fn write(&mut self, docids: &[String], embeddings: &[candle_core::Tensor]) -> Result<()> {
+        /*
+        Append the (tokens, dimension) vectors of a batch of documents
+        */
+        for (docid, embedding) in docids.iter().zip(embeddings) {
+            let (tokens, dimension) = embedding.dims2()?;
+            if dimension != self.dimension as usize {
+                return Err(Error::DimensionMismatch {
+                    expected: self.dimension as usize,
+                    actual: dimension,
+                });
+            }
+
+            let vectors = embedding.flatten_all()?.to_vec1::<f32>()?;
+            for value in &vectors {
+                self.vectors.write_all(&value.to_le_bytes())?;
+            }
+            self.index.add(&vectors)?;
+
+            self.docids.push(docid.clone());
+            self.doclens.push(tokens);
+        }
+
+        Ok(())
+    }
+
+    pub fn save(&mut self) -> Result<()> {
+        /*
+        Flush the vectors and write the faiss index, the docids and the document lengths
+        */
+        self.vectors.flush()?;
+
+        let index_file_path: PathBuf = self.dir_path.join("index");
+        write_index(&self.index, index_file_path.as_path().display().to_string())?;
+
+        let mut docid_file = File::create(self.dir_path.join("docid"))?;
+        for docid in &self.docids {
+            writeln!(docid_file, "{}", docid)?;
+        }
+        let mut doclens_file = File::create(self.dir_path.join("doclens"))?;
+        for doclen in &self.doclens {
+            writeln!(doclens_file, "{}", doclen)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl JsonVectorCollectionWriter {
+    pub fn new(path: &str) -> Self {
+        /*
+        Write to <path>/embeddings.jsonl with the quantization factor of Pyserini, 100
+        */
+        Self {
+            dir_path: PathBuf::from(path),
+            filename: "embeddings.jsonl".to_string(),
+            file: None,
+            quantization_factor: 100.0,
+        }
+    }
+
+    pub fn with_quantization_factor(mut self, quantization_factor: f32) -> Self {
+        self.quantization_factor = quantization_factor;
+        self
+    }
+
+    pub fn open_file(&mut self) -> Result<()> {
+        if !self.dir_path.exists() {
+            std::fs::create_dir_all(&self.dir_path)?;
+        }
+
+        let file_path = self.dir_path.join(&self.filename);
+        self.file = Some(std::fs::File::create(file_path)?);
+
+        Ok(())
+    }
+
+    pub fn write(&mut self, batch_info: &HashMap<&str, Vec<String>>, weights: &[HashMap<String, f32>]) -> Result<()> {
+        /*
+        Write one {"id", "contents", "vector"} record per document of the batch
+        */
+        let mut file = match &self.file {
+            Some(file) => file,
+            None => {
+                return Err(Error::Io(std::io::Error::other("File is not open for writing!")));
+            }
+        };
+
+        for (i, weights) in weights.iter().enumerate() {
+            let record = json!({
+                "id": batch_info["id"][i],
+                "contents": batch_info["text"][i],
+                "vector": quantize_weights(weights, self.quantization_factor),
+            });
+            writeln!(file, "{}", record)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl M3RepresentationWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_dense(mut self, writer: impl RepresentationWriter + 'static) -> Self {
+        self.dense = Some(Box::new(writer));
+        self
+    }
+
+    pub fn with_sparse(mut self, writer: JsonVectorCollectionWriter) -> Self {
+        self.sparse = Some(writer);
+        self
+    }
+
+    pub fn with_multi_vector(mut self, writer: ColBertIndexWriter) -> Self {
+        self.multi_vector = Some(writer);
+        self
+    }
+
+    pub fn open_file(&mut self) -> Result<()> {
+        /*
+        Open the docid file of the dense writer and the collection file of the sparse writer
+        */
+        if let Some(dense) = &mut self.dense {
+            dense.open_file()?;
+        }
+        if let Some(sparse) = &mut self.sparse {
+            sparse.open_file()?;
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, batch_info: &HashMap<&str, Vec<String>>, embeddings: &M3Embeddings) -> Result<()> {
+        /*
+        Hand each representation of a batch to its writer
+        */
+        if let Some(dense) = &mut self.dense {
+            let mut vectors = embeddings.dense.flatten_all()?.to_vec1::<f32>()?;
+            dense.write(batch_info, &mut vectors)?;
+        }
+        if let Some(sparse) = &mut self.sparse {
+            sparse.write(batch_info, &embeddings.sparse)?;
+        }
+        if let Some(multi_vector) = &mut self.multi_vector {
+            multi_vector.write(&batch_info["id"], &embeddings.multi_vector)?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&mut self) -> Result<()> {
+        /*
+        Write the dense index and its docids, and the multi-vector index
+        */
+        if let Some(dense) = &mut self.dense {
+            dense.save_index()?;
+            dense.save_docids()?;
+        }
+        if let Some(multi_vector) = &mut self.multi_vector {
+            multi_vector.save()?;
+        }
+        Ok(())
+    }
+}
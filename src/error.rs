@@ -0,0 +1,77 @@
+use thiserror::Error as ThisError;
+
+/// The error type returned by every public API of rustserini
+#[derive(Debug, ThisError)]
+pub enum Error {
+    /// A checkpoint could not be resolved, downloaded or loaded
+    #[error("Model load error: {0}")]
+    ModelLoad(String),
+
+    /// A tokenizer could not be loaded or failed to encode its input
+    #[error("Tokenizer error: {0}")]
+    Tokenizer(String),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// A Faiss index could not be created, read, written or searched
+    #[error("Index error: {0}")]
+    Index(String),
+
+    /// The JVM could not be started or an Anserini call failed
+    #[error("JVM error: {0}")]
+    Jvm(String),
+
+    #[error("Dimension mismatch: expected {expected}, got {actual}")]
+    DimensionMismatch { expected: usize, actual: usize },
+
+    /// A corpus or topics file contains an invalid record
+    #[error("Corpus parse error: {0}")]
+    CorpusParse(String),
+
+    /// An argument or configuration value is not supported
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    /// An argument does not fit the call, e.g. two lists that should have the same length
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+
+    /// A query, document or file looked up by the caller does not exist
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    /// An encoder ran but did not produce the expected output
+    #[error("Encoding error: {0}")]
+    Encoding(String),
+
+    /// A worker thread panicked or a lock was poisoned
+    #[error("Internal error: {0}")]
+    Internal(String),
+
+    #[error("Tensor error: {0}")]
+    Tensor(#[from] candle_core::Error),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl From<faiss::error::Error> for Error {
+    fn from(err: faiss::error::Error) -> Self {
+        Error::Index(err.to_string())
+    }
+}
+
+impl From<j4rs::errors::J4RsError> for Error {
+    fn from(err: j4rs::errors::J4RsError) -> Self {
+        Error::Jvm(err.to_string())
+    }
+}
+
+impl From<hf_hub::api::sync::ApiError> for Error {
+    fn from(err: hf_hub::api::sync::ApiError) -> Self {
+        Error::ModelLoad(err.to_string())
+    }
+}
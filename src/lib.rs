@@ -1,2 +1,6 @@
 pub mod encode;
+pub mod error;
+pub mod rerank;
 pub mod searcher;
+
+pub use error::{Error, Result};
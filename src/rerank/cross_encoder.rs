@@ -0,0 +1,101 @@
+use crate::encode::auto::{load_var_builder, OutputModelType};
+use crate::encode::config::{EncoderConfig, FieldJoin};
+use crate::encode::core::EncoderCore;
+use crate::encode::source::ModelSource;
+use crate::error::{Error, Result};
+use crate::rerank::{sort_candidates, Candidate, Reranker};
+
+use candle_core::{DType, IndexOp, Module, D};
+use candle_nn::{Linear, VarBuilder};
+
+/// A CrossEncoderReranker scores (query, document) pairs with a BertForSequenceClassification checkpoint
+/// such as cross-encoder/ms-marco-MiniLM-L-6-v2 or castorini/monobert-large-msmarco
+/// The [CLS] hidden state goes through the BERT pooler and the classification head, a single label is used
+/// as the score and two labels are turned into the log probability of relevance
+/// It is designed to be a parallel of this Python Class
+/// https://github.com/castorini/pygaggle/blob/master/pygaggle/rerank/transformer.py
+pub struct CrossEncoderReranker {
+    core: EncoderCore,
+    pooler: Linear,
+    classifier: Linear,
+    batch_size: usize,
+}
+
+fn load_linear(vb: &VarBuilder, name: &str) -> Result<Linear> {
+    let vb = vb.pp(name);
+    let missing = |_| Error::ModelLoad(format!("Cross-encoder checkpoint does not provide a {} layer", name));
+    let weight = vb.get_unchecked("weight").map_err(missing)?;
+    let bias = vb.get_unchecked("bias").map_err(missing)?;
+    Ok(Linear::new(weight, Some(bias)))
+}
+
+impl CrossEncoderReranker {
+    pub fn from_source(source: &ModelSource) -> Result<Self> {
+        Self::from_sources(source, source)
+    }
+
+    pub fn from_sources(model_source: &ModelSource, tokenizer_source: &ModelSource) -> Result<Self> {
+        /*
+        Queries and documents are tokenized as sentence pairs truncated to 512 tokens
+        */
+        let core = EncoderCore::from_sources(model_source, tokenizer_source, OutputModelType::BertModel)?
+            .with_config(EncoderConfig::new(512).with_field_join(FieldJoin::Pair))?;
+
+        let vb = load_var_builder(&model_source.weights()?, DType::F32, core.device())?;
+        let pooler = load_linear(&vb, "pooler.dense")?;
+        let classifier = load_linear(&vb, "classifier")?;
+
+        Ok(Self { core, pooler, classifier, batch_size: 32 })
+    }
+
+    pub fn with_config(mut self, config: EncoderConfig) -> Result<Self> {
+        /*
+        Set the maximum lengths and truncation of the pairs, the field join is always a sentence pair
+        */
+        self.core = self.core.with_config(config.with_field_join(FieldJoin::Pair))?;
+        Ok(self)
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        /*
+        Set how many pairs are scored at once, defaults to 32
+        */
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn score(&self, query: &str, texts: &[String]) -> Result<Vec<f32>> {
+        /*
+        Score the relevance of each text to the query
+        */
+        let mut scores = Vec::with_capacity(texts.len());
+
+        for batch in texts.chunks(self.batch_size) {
+            let queries = vec![query.to_string(); batch.len()];
+            // The query is the first sequence of each pair, like a title
+            let tokens = self.core.tokenize_documents(batch, Some(&queries))?;
+            let (hidden_state, _attention_mask) = self.core.forward(&tokens)?;
+
+            let cls = hidden_state.to_dtype(DType::F32)?.i((.., 0))?;
+            let pooled = self.pooler.forward(&cls)?.tanh()?;
+            let logits = self.classifier.forward(&pooled)?;
+
+            let batch_scores = match logits.dim(1)? {
+                1 => logits.squeeze(1)?,
+                labels => candle_nn::ops::log_softmax(&logits, D::Minus1)?.i((.., labels - 1))?,
+            };
+            scores.extend(batch_scores.to_vec1::<f32>()?);
+        }
+
+        Ok(scores)
+    }
+}
+
+impl Reranker for CrossEncoderReranker {
+    fn rerank(&self, query: &str, candidates: Vec<Candidate>) -> Result<Vec<Candidate>> {
+        let texts: Vec<String> = candidates.iter().map(|candidate| candidate.text.clone()).collect();
+        let scores = self.score(query, &texts)?;
+
+        Ok(sort_candidates(candidates, &scores))
+    }
+}
@@ -0,0 +1,87 @@
+pub mod cross_encoder;
+pub mod t5;
+
+pub use cross_encoder::CrossEncoderReranker;
+pub use t5::{DuoT5Reranker, MonoT5Reranker};
+
+use crate::error::{Error, Result};
+use crate::searcher::faiss::searcher::DenseSearchResult;
+use crate::searcher::lucene::searcher::LuceneSearcherResult;
+
+/// A first stage search result that can be reranked
+pub trait SearchHit {
+    fn docid(&self) -> &str;
+    fn score(&self) -> f32;
+}
+
+impl SearchHit for LuceneSearcherResult {
+    fn docid(&self) -> &str {
+        &self.docid
+    }
+
+    fn score(&self) -> f32 {
+        self.score
+    }
+}
+
+impl SearchHit for DenseSearchResult {
+    fn docid(&self) -> &str {
+        &self.docid
+    }
+
+    fn score(&self) -> f32 {
+        self.score
+    }
+}
+
+/// A document to rerank, with its text and its current score
+/// It is designed to be a parallel of pygaggle's Text
+/// https://github.com/castorini/pygaggle/blob/master/pygaggle/rerank/base.py
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub docid: String,
+    pub text: String,
+    pub score: f32,
+}
+
+impl Candidate {
+    pub fn new(docid: impl Into<String>, text: impl Into<String>, score: f32) -> Self {
+        Self {
+            docid: docid.into(),
+            text: text.into(),
+            score,
+        }
+    }
+}
+
+pub fn candidates<H: SearchHit>(hits: &[H], texts: &[String]) -> Result<Vec<Candidate>> {
+    /*
+    Pair the hits of a searcher with the texts of their documents, in the same order
+    */
+    if hits.len() != texts.len() {
+        return Err(Error::InvalidInput(format!("{} hits were given {} texts", hits.len(), texts.len())));
+    }
+
+    Ok(hits
+        .iter()
+        .zip(texts)
+        .map(|(hit, text)| Candidate::new(hit.docid(), text.as_str(), hit.score()))
+        .collect())
+}
+
+/// A base trait for second stage rerankers
+pub trait Reranker {
+    // Score the candidates against the query and return them sorted by decreasing reranker score
+    fn rerank(&self, query: &str, candidates: Vec<Candidate>) -> Result<Vec<Candidate>>;
+}
+
+pub fn sort_candidates(mut candidates: Vec<Candidate>, scores: &[f32]) -> Vec<Candidate> {
+    /*
+    Replace the scores of the candidates and sort them by decreasing score, ties keep their first stage order
+    */
+    for (candidate, score) in candidates.iter_mut().zip(scores) {
+        candidate.score = *score;
+    }
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    candidates
+}
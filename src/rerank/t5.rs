@@ -0,0 +1,239 @@
+use crate::encode::auto::load_var_builder;
+use crate::encode::source::ModelSource;
+use crate::error::{Error, Result};
+use crate::rerank::{sort_candidates, Candidate, Reranker};
+
+use candle_core::{DType, Device, Tensor, D};
+use candle_transformers::models::t5::{Config, T5ForConditionalGeneration};
+use std::sync::Mutex;
+use tokenizers::Tokenizer;
+
+/// A sequence-to-sequence relevance scorer, the T5 model reads a prompt and the score is the log probability
+/// of generating `true` rather than `false` as the first token
+/// candle's T5 does not take an attention mask, so prompts are scored one at a time without padding
+struct T5Scorer {
+    model: Mutex<T5ForConditionalGeneration>,
+    tokenizer: Tokenizer,
+    device: Device,
+    decoder_start_token_id: u32,
+    token_false: u32,
+    token_true: u32,
+    suffix: Vec<u32>,
+    max_length: usize,
+}
+
+impl T5Scorer {
+    fn from_sources(model_source: &ModelSource, tokenizer_source: &ModelSource) -> Result<Self> {
+        let device = model_source.device.clone();
+
+        let config: Config = serde_json::from_str(&std::fs::read_to_string(model_source.get("config.json")?)?)?;
+        let vb = load_var_builder(&model_source.weights()?, DType::F32, &device)?;
+        let model = T5ForConditionalGeneration::load(vb, &config)?;
+
+        let mut tokenizer = Tokenizer::from_file(tokenizer_source.get("tokenizer.json")?)
+            .map_err(|err| Error::Tokenizer(err.to_string()))?;
+        tokenizer
+            .with_truncation(None)
+            .map_err(|err| Error::Tokenizer(err.to_string()))?;
+        tokenizer.with_padding(None);
+
+        let token_id = |token: &str| {
+            tokenizer
+                .token_to_id(token)
+                .ok_or_else(|| Error::Tokenizer(format!("The tokenizer has no {} token", token)))
+        };
+        let token_false = token_id("▁false")?;
+        let token_true = token_id("▁true")?;
+
+        // The prompts end with "Relevant:", which is kept when a long document is cut
+        let suffix = tokenizer
+            .encode("Relevant:", true)
+            .map_err(|err| Error::Tokenizer(err.to_string()))?
+            .get_ids()
+            .to_vec();
+
+        Ok(Self {
+            model: Mutex::new(model),
+            tokenizer,
+            device,
+            decoder_start_token_id: config.decoder_start_token_id.unwrap_or(config.pad_token_id) as u32,
+            token_false,
+            token_true,
+            suffix,
+            max_length: 512,
+        })
+    }
+
+    fn score(&self, prompt: &str) -> Result<f32> {
+        /*
+        The log probability of `true` among {`false`, `true`} for the prompt, truncated to max_length tokens
+        */
+        let encoding = self
+            .tokenizer
+            .encode(prompt, true)
+            .map_err(|err| Error::Tokenizer(err.to_string()))?;
+        let mut ids = encoding.get_ids().to_vec();
+        if ids.len() > self.max_length {
+            ids.truncate(self.max_length.saturating_sub(self.suffix.len()));
+            ids.extend_from_slice(&self.suffix);
+        }
+
+        let input_ids = Tensor::new(ids.as_slice(), &self.device)?.unsqueeze(0)?;
+        let decoder_input_ids = Tensor::new(&[self.decoder_start_token_id], &self.device)?.unsqueeze(0)?;
+
+        let mut model = self
+            .model
+            .lock()
+            .map_err(|_| Error::Internal("The T5 model lock was poisoned".to_string()))?;
+        model.clear_kv_cache();
+        let encoder_output = model.encode(&input_ids)?;
+        let logits = model.decode(&decoder_input_ids, &encoder_output)?.squeeze(0)?;
+
+        let logits = Tensor::stack(&[logits.get(self.token_false as usize)?, logits.get(self.token_true as usize)?], 0)?;
+        let log_probs = candle_nn::ops::log_softmax(&logits.to_dtype(DType::F32)?, D::Minus1)?;
+
+        Ok(log_probs.get(1)?.to_scalar::<f32>()?)
+    }
+}
+
+/// A MonoT5Reranker scores each candidate on its own with the prompt "Query: q Document: d Relevant:"
+/// such as castorini/monot5-base-msmarco, whose tokenizer is the one of t5-base
+/// It is designed to be a parallel of this Python Class
+/// https://github.com/castorini/pygaggle/blob/master/pygaggle/rerank/transformer.py
+pub struct MonoT5Reranker {
+    scorer: T5Scorer,
+}
+
+impl MonoT5Reranker {
+    pub fn from_source(source: &ModelSource) -> Result<Self> {
+        Self::from_sources(source, source)
+    }
+
+    pub fn from_sources(model_source: &ModelSource, tokenizer_source: &ModelSource) -> Result<Self> {
+        /*
+        The castorini checkpoints ship without tokenizer.json, their tokenizer source is t5-base
+        */
+        Ok(Self { scorer: T5Scorer::from_sources(model_source, tokenizer_source)? })
+    }
+
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        /*
+        Set the maximum number of prompt tokens, defaults to 512
+        */
+        self.scorer.max_length = max_length;
+        self
+    }
+
+    pub fn score(&self, query: &str, texts: &[String]) -> Result<Vec<f32>> {
+        /*
+        The log probability of each text being relevant to the query
+        */
+        texts
+            .iter()
+            .map(|text| self.scorer.score(&format!("Query: {} Document: {} Relevant:", query, text)))
+            .collect()
+    }
+}
+
+impl Reranker for MonoT5Reranker {
+    fn rerank(&self, query: &str, candidates: Vec<Candidate>) -> Result<Vec<Candidate>> {
+        let texts: Vec<String> = candidates.iter().map(|candidate| candidate.text.clone()).collect();
+        let scores = self.score(query, &texts)?;
+
+        Ok(sort_candidates(candidates, &scores))
+    }
+}
+
+pub fn sym_sum<F>(count: usize, mut preference: F) -> Result<Vec<f32>>
+where
+    F: FnMut(usize, usize) -> Result<f32>,
+{
+    /*
+    Aggregate pairwise preferences over every ordered pair (i, j), the probability p that i beats j
+    adds p to the score of i and 1 - p to the score of j
+    */
+    let mut scores = vec![0.0; count];
+
+    for i in 0..count {
+        for j in 0..count {
+            if i == j {
+                continue;
+            }
+            let probability = preference(i, j)?;
+            scores[i] += probability;
+            scores[j] += 1.0 - probability;
+        }
+    }
+
+    Ok(scores)
+}
+
+pub fn rerank_top_k<F>(mut candidates: Vec<Candidate>, top_k: usize, score: F) -> Result<Vec<Candidate>>
+where
+    F: FnOnce(&[String]) -> Result<Vec<f32>>,
+{
+    /*
+    Rerank the first top_k candidates with the scores of their texts, the others follow in their original order
+    */
+    let rest = candidates.split_off(top_k.min(candidates.len()));
+
+    let texts: Vec<String> = candidates.iter().map(|candidate| candidate.text.clone()).collect();
+    let scores = score(&texts)?;
+
+    let mut reranked = sort_candidates(candidates, &scores);
+    reranked.extend(rest);
+    Ok(reranked)
+}
+
+/// A DuoT5Reranker compares every ordered pair of the top candidates with the prompt
+/// "Query: q Document0: d0 Document1: d1 Relevant:", such as castorini/duot5-base-msmarco
+/// The probability p that d0 is more relevant than d1 adds p to the score of d0 and 1 - p to the score of d1,
+/// the candidates below the top ones keep their order after them
+/// It is designed to be a parallel of this Python Class
+/// https://github.com/castorini/pygaggle/blob/master/pygaggle/rerank/transformer.py
+pub struct DuoT5Reranker {
+    scorer: T5Scorer,
+    top_k: usize,
+}
+
+impl DuoT5Reranker {
+    pub fn from_source(source: &ModelSource) -> Result<Self> {
+        Self::from_sources(source, source)
+    }
+
+    pub fn from_sources(model_source: &ModelSource, tokenizer_source: &ModelSource) -> Result<Self> {
+        Ok(Self { scorer: T5Scorer::from_sources(model_source, tokenizer_source)?, top_k: 10 })
+    }
+
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        /*
+        Set the maximum number of prompt tokens, defaults to 512
+        */
+        self.scorer.max_length = max_length;
+        self
+    }
+
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        /*
+        Set how many of the first candidates are compared pairwise, defaults to 10, i.e. 90 pairs
+        */
+        self.top_k = top_k;
+        self
+    }
+
+    pub fn score(&self, query: &str, texts: &[String]) -> Result<Vec<f32>> {
+        /*
+        The sum of the pairwise preference probabilities of each text against all the others
+        */
+        sym_sum(texts.len(), |i, j| {
+            let prompt = format!("Query: {} Document0: {} Document1: {} Relevant:", query, texts[i], texts[j]);
+            Ok(self.scorer.score(&prompt)?.exp())
+        })
+    }
+}
+
+impl Reranker for DuoT5Reranker {
+    fn rerank(&self, query: &str, candidates: Vec<Candidate>) -> Result<Vec<Candidate>> {
+        rerank_top_k(candidates, self.top_k, |texts| self.score(query, texts))
+    }
+}
@@ -0,0 +1,188 @@
+use crate::encode::colbert::{ColBertConfig, ColBertEncoder};
+use crate::encode::config::EncoderConfig;
+use crate::searcher::faiss::searcher::{DenseSearchResult, FaissSearcher};
+
+use crate::error::{Error, Result};
+use faiss::index::IndexImpl;
+use faiss::Index;
+use memmap2::Mmap;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// A ColBertSearcher searches an index written by ColBertIndexWriter with late interaction
+/// Every query vector retrieves its nearest document vectors from the faiss index, the documents they belong to
+/// are the candidates, and the candidates are ranked by their exact MaxSim score over the stored vectors
+/// The `vectors` file is memory-mapped, only the documents scored by a query are read from it, but the faiss
+/// index holding every document vector is loaded in memory
+pub struct ColBertSearcher {
+    query_encoder: ColBertEncoder,
+    dimension: usize,
+    index: IndexImpl,
+    docids: Vec<String>,
+    offsets: Vec<usize>,
+    vectors: Mmap,
+    candidates_per_vector: usize,
+}
+
+pub fn maxsim(query: &[f32], document: &[f32], dimension: usize) -> f32 {
+    /*
+    Sum over the query vectors of their highest inner product with a document vector
+    */
+    query
+        .chunks(dimension)
+        .map(|q| {
+            document
+                .chunks(dimension)
+                .map(|d| q.iter().zip(d).map(|(q, d)| q * d).sum::<f32>())
+                .fold(f32::NEG_INFINITY, f32::max)
+        })
+        .filter(|score| score.is_finite())
+        .sum()
+}
+
+impl ColBertSearcher {
+    pub fn new(index_dir: impl AsRef<Path>, query_encoder: ColBertEncoder) -> Result<Self> {
+        /*
+        Load a ColBERT index, encoding queries with the EncoderConfig and ColBertConfig stored in it if any
+        */
+        let index_dir = index_dir.as_ref();
+        let query_encoder = match EncoderConfig::load(index_dir)? {
+            Some(config) => query_encoder.with_config(config)?,
+            None => query_encoder,
+        };
+        let query_encoder = match ColBertConfig::load(index_dir)? {
+            Some(config) => query_encoder.with_colbert_config(config)?,
+            None => query_encoder,
+        };
+        let dimension = query_encoder.dimension();
+
+        let index = FaissSearcher::load_index(index_dir)?;
+        let docids = FaissSearcher::load_docids(index_dir)?;
+        let offsets = Self::load_offsets(index_dir)?;
+        let vectors = Self::load_vectors(index_dir)?;
+
+        let expected = offsets.last().copied().unwrap_or(0) * dimension * size_of::<f32>();
+        if offsets.len() != docids.len() + 1 || vectors.len() != expected {
+            return Err(Error::Index(format!(
+                "{} docids, {} document lengths and {} bytes do not describe the same {}-dimensional f32 vectors",
+                docids.len(),
+                offsets.len() - 1,
+                vectors.len(),
+                dimension
+            )));
+        }
+
+        Ok(Self {
+            query_encoder,
+            dimension,
+            index,
+            docids,
+            offsets,
+            vectors,
+            candidates_per_vector: 64,
+        })
+    }
+
+    pub fn with_candidates_per_vector(mut self, candidates_per_vector: usize) -> Self {
+        /*
+        Set how many nearest document vectors each query vector retrieves during candidate generation
+        */
+        self.candidates_per_vector = candidates_per_vector.max(1);
+        self
+    }
+
+    fn load_offsets(index_dir: &Path) -> Result<Vec<usize>> {
+        /*
+        Turn the document lengths into the offsets of each document in the vectors file
+        */
+        let doclens = std::fs::read_to_string(index_dir.join("doclens"))?;
+        let mut offsets = vec![0];
+        for line in doclens.lines() {
+            let doclen: usize = line
+                .trim()
+                .parse()
+                .map_err(|_| Error::Index(format!("Invalid document length {:?}", line)))?;
+            offsets.push(offsets[offsets.len() - 1] + doclen);
+        }
+        Ok(offsets)
+    }
+
+    fn load_vectors(index_dir: &Path) -> Result<Mmap> {
+        /*
+        Map the vectors file instead of reading it, it holds every token vector of the corpus
+        */
+        let file = std::fs::File::open(index_dir.join("vectors"))?;
+        // The index files are written once by ColBertIndexWriter and never modified while they are searched
+        let vectors = unsafe { Mmap::map(&file)? };
+        Ok(vectors)
+    }
+
+    fn document(&self, doc: usize) -> Vec<f32> {
+        /*
+        Decode the little-endian vectors of a document from the mapped file
+        */
+        let width = self.dimension * size_of::<f32>();
+        self.vectors[self.offsets[doc] * width..self.offsets[doc + 1] * width]
+            .chunks_exact(size_of::<f32>())
+            .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+            .collect()
+    }
+
+    fn rank(&mut self, query: &[f32], k: usize) -> Result<Vec<DenseSearchResult>> {
+        /*
+        Gather the candidate documents of a query and rank them by MaxSim
+        */
+        let result = self.index.search(query, self.candidates_per_vector)?;
+
+        let candidates: HashSet<usize> = result
+            .labels
+            .iter()
+            .filter_map(|label| label.get())
+            .map(|vector| self.offsets.partition_point(|&offset| offset <= vector as usize) - 1)
+            .collect();
+
+        let mut scores: Vec<(usize, f32)> = candidates
+            .into_iter()
+            .map(|doc| (doc, maxsim(query, &self.document(doc), self.dimension)))
+            .collect();
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scores.truncate(k);
+
+        Ok(scores
+            .into_iter()
+            .map(|(doc, score)| DenseSearchResult::new(self.docids[doc].clone(), score))
+            .collect())
+    }
+
+    pub fn search(&mut self, query: String, k: usize) -> Result<Vec<DenseSearchResult>> {
+        /*
+        Search a query and return the top k results
+        */
+        let mut queries = self.query_encoder.encode_queries(&[query])?;
+        let query = queries
+            .pop()
+            .ok_or(Error::Encoding("No vectors were produced for the query".to_string()))?;
+
+        self.rank(&query.flatten_all()?.to_vec1::<f32>()?, k)
+    }
+
+    pub fn batch_search(
+        &mut self,
+        queries: Vec<String>,
+        q_ids: Vec<String>,
+        k: usize,
+    ) -> Result<HashMap<String, Vec<DenseSearchResult>>> {
+        /*
+        Search a batch of queries and return the top k results of each
+        */
+        let embeddings = self.query_encoder.encode_queries(&queries)?;
+
+        let mut results = HashMap::new();
+        for (q_id, query) in q_ids.into_iter().zip(embeddings) {
+            let hits = self.rank(&query.flatten_all()?.to_vec1::<f32>()?, k)?;
+            results.insert(q_id, hits);
+        }
+
+        Ok(results)
+    }
+}
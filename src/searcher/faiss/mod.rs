@@ -1,2 +1,3 @@
+pub mod colbert;
 pub mod model;
 pub mod searcher;
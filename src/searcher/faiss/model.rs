@@ -1,10 +1,16 @@
-use crate::encode::auto::{
-    build_model_and_tokenizer, mean_pooling, Model
-};
+use crate::encode::auto::OutputModelType;
+use crate::encode::config::EncoderConfig;
+use crate::encode::core::EncoderCore;
+use crate::encode::pooling::{normalize_l2, Pooling};
+use crate::encode::source::ModelSource;
 
 use candle_core::{Device, Tensor};
-use tokenizers::Tokenizer;
-use anyhow::{Error as E, Result};
+use crate::error::{Error, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
 
 
 pub enum QueryType {
@@ -18,86 +24,264 @@ pub trait QueryEncoder {
     fn new(
         model_name: &str,
         revision: &str,
-    ) -> Self;
+    ) -> Result<Self>
+    where
+        Self: Sized;
 
     // Encode a document or a set of documents into a vector of floats
-    fn encode(&self, query: QueryType, pooler_type: &str) -> Result<Tensor, E>;
+    fn encode(&self, query: QueryType, pooling: Pooling, normalize: bool) -> Result<Tensor>;
 }
 
 pub struct AutoQueryEncoder {
-    model: Model,
-    tokenizer: Tokenizer,
-    device: Device
+    core: EncoderCore,
+}
+
+impl AutoQueryEncoder {
+    pub fn from_source(source: &ModelSource) -> Result<Self> {
+        Self::from_sources(source, source)
+    }
+
+    pub fn from_sources(model_source: &ModelSource, tokenizer_source: &ModelSource) -> Result<Self> {
+        let core = EncoderCore::from_sources(model_source, tokenizer_source, OutputModelType::BertModel)?;
+        Ok(Self { core })
+    }
+
+    pub fn with_config(mut self, config: EncoderConfig) -> Result<Self> {
+        self.core = self.core.with_config(config)?;
+        Ok(self)
+    }
 }
 
 impl QueryEncoder for AutoQueryEncoder {
     fn new(
         model_name: &str,
         revision: &str,
-    ) -> Self {
-        let device = Device::Cpu;
-        let (model, tokenizer) = build_model_and_tokenizer(model_name, false, revision).unwrap();
-        Self { model, tokenizer, device }
+    ) -> Result<Self> {
+        Self::from_source(&ModelSource::new(model_name, revision))
     }
 
-    fn encode(&self, queries: QueryType, pooler_type: &str) -> Result<Tensor, E> {
+    fn encode(&self, queries: QueryType, pooling: Pooling, normalize: bool) -> Result<Tensor> {
         let texts = match queries {
             QueryType::Query { query } => vec![query],
             QueryType::Queries { query } => query,
         };
+        let tokens = self.core.tokenize_queries(&texts)?;
+
+        self.core.embed(&tokens, pooling, normalize)
+    }
+}
+
+/// A PreEncodedQueryEncoder looks up query embeddings computed ahead of time instead of running a model
+/// Embeddings are keyed both by query text and by qid, so either can be passed as the query
+/// It is designed to be a parallel of Pyserini's QueryEncoder over an encoded_query_dir
+/// https://github.com/castorini/pyserini/blob/master/pyserini/search/faiss/_searcher.py
+pub struct PreEncodedQueryEncoder {
+    embeddings: Vec<Vec<f32>>,
+    keys: HashMap<String, usize>,
+    dimension: usize,
+}
+
+fn parse_npy(bytes: &[u8]) -> Result<(Vec<f32>, usize, usize)> {
+    /*
+    Read a 2-dimensional little-endian float32 or float64 C-ordered .npy array into (values, rows, columns)
+    */
+    let invalid = |reason: &str| Error::CorpusParse(format!("Invalid .npy file: {}", reason));
 
+    if bytes.len() < 10 || &bytes[..6] != b"\x93NUMPY" {
+        return Err(invalid("missing the NUMPY magic string"));
+    }
+    let (header_start, header_length) = match bytes[6] {
+        1 => (10, u16::from_le_bytes([bytes[8], bytes[9]]) as usize),
+        _ if bytes.len() >= 12 => (12, u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize),
+        _ => return Err(invalid("truncated header")),
+    };
+    let data_start = header_start + header_length;
+    let header = bytes
+        .get(header_start..data_start)
+        .and_then(|header| std::str::from_utf8(header).ok())
+        .ok_or_else(|| invalid("unreadable header"))?;
+
+    if header.contains("'fortran_order': True") {
+        return Err(invalid("Fortran ordered arrays are not supported"));
+    }
+    let width = if header.contains("'<f4'") {
+        4
+    } else if header.contains("'<f8'") {
+        8
+    } else {
+        return Err(invalid("only little-endian float32 and float64 arrays are supported"));
+    };
+
+    let shape = header
+        .split("'shape':")
+        .nth(1)
+        .and_then(|shape| shape.split(')').next())
+        .ok_or_else(|| invalid("missing shape"))?;
+    let shape: Vec<usize> = shape
+        .trim_start_matches([' ', '('])
+        .split(',')
+        .filter(|dim| !dim.trim().is_empty())
+        .map(|dim| dim.trim().parse::<usize>().map_err(|_| invalid("unreadable shape")))
+        .collect::<Result<_>>()?;
+    let (rows, columns) = match shape.as_slice() {
+        [rows, columns] => (*rows, *columns),
+        _ => return Err(invalid("expected a 2-dimensional array")),
+    };
+
+    let data = bytes
+        .get(data_start..data_start + rows * columns * width)
+        .ok_or_else(|| invalid("the array is shorter than its shape"))?;
+    let values = match width {
+        4 => data
+            .chunks_exact(4)
+            .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+            .collect(),
+        _ => data
+            .chunks_exact(8)
+            .map(|value| f64::from_le_bytes(value.try_into().unwrap_or_default()) as f32)
+            .collect(),
+    };
+
+    Ok((values, rows, columns))
+}
 
-        let tokens = self.tokenizer
-            .encode_batch(texts, true)
-            .map_err(E::msg)?;
-        let token_ids = tokens
-            .iter()
-            .map(|tokens| {
-                let tokens = tokens.get_ids().to_vec();
-                Ok(Tensor::new(tokens.as_slice(), &self.device)?)
-            })
-            .collect::<Result<Vec<_>>>()?;
-        let attention_mask = tokens
-            .iter()
-            .map(|tokens| {
-                let tokens = tokens.get_attention_mask().to_vec();
-                Ok(Tensor::new(tokens.as_slice(), &self.device)?)
-            })
-            .collect::<Result<Vec<_>>>()?;
-
-        let token_ids = Tensor::stack(&token_ids, 0)?;
-        let token_type_ids = token_ids.zeros_like()?;
-        let attention_mask  = Tensor::stack(&attention_mask, 0)?;
-
-        let hidden_state: Tensor = match &self.model {
-            Model::BertModel {model} => {
-                let hidden_state: Tensor = model.forward(&token_ids, &token_type_ids, Some(&attention_mask))?;
-                hidden_state
-            },
-            Model::BertForMaskedLM {model} => {
-                let hidden_state: Tensor = model.forward(&token_ids, &token_type_ids, Some(&attention_mask))?;
-                hidden_state
-            },            
+impl PreEncodedQueryEncoder {
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        /*
+        Load pre-encoded queries from a .jsonl or .npy file, or from a directory holding embedding.jsonl or embedding.npy
+        */
+        let path = path.as_ref();
+        let path = if path.is_dir() {
+            ["embedding.jsonl", "embedding.npy"]
+                .iter()
+                .map(|filename| path.join(filename))
+                .find(|path| path.exists())
+                .ok_or_else(|| Error::NotFound(format!("{:?} holds neither embedding.jsonl nor embedding.npy", path)))?
+        } else {
+            path.to_path_buf()
         };
 
-        let embeddings: Tensor = if pooler_type == "mean" {
-            mean_pooling(hidden_state, false)?
-            
-        } else if pooler_type == "cls" {
-            
-            let (_n_sentence, _n_tokens, _hidden_size) = hidden_state.dims3()?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("npy") => Self::from_npy(&path, path.with_extension("tsv")),
+            _ => Self::from_jsonl(&path),
+        }
+    }
 
-            let mut out_embeding = vec![];
-            for i in 0.._n_sentence{
+    pub fn from_jsonl(path: impl AsRef<Path>) -> Result<Self> {
+        /*
+        Load one {"id", "contents" or "text", "vector"} record per line, as written by JsonlRepresentationWriter
+        */
+        let path = path.as_ref();
+        let reader = BufReader::new(File::open(path)?);
+        let mut encoder = Self { embeddings: Vec::new(), keys: HashMap::new(), dimension: 0 };
 
-                let embeding = hidden_state.get(i)?.get(0)?;
-                out_embeding.push(embeding);
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
             }
-            Tensor::stack(&out_embeding, 0)?
-        } else {
-            panic!("pooler_type must be either mean or cls");
+            let invalid = |reason: String| Error::CorpusParse(format!("{:?} line {}: {}", path, line_number + 1, reason));
+
+            let json: Value = serde_json::from_str(&line).map_err(|err| invalid(err.to_string()))?;
+            let vector = json["vector"]
+                .as_array()
+                .ok_or_else(|| invalid("missing vector".to_string()))?
+                .iter()
+                .map(|value| value.as_f64().map(|value| value as f32))
+                .collect::<Option<Vec<f32>>>()
+                .ok_or_else(|| invalid("the vector holds a non-numeric value".to_string()))?;
+
+            let keys = ["id", "contents", "text"].iter().filter_map(|field| match &json[*field] {
+                Value::String(key) => Some(key.clone()),
+                Value::Number(key) => Some(key.to_string()),
+                _ => None,
+            });
+            encoder.push(keys.collect(), vector)?;
+        }
+
+        Ok(encoder)
+    }
+
+    pub fn from_npy(embeddings_path: impl AsRef<Path>, queries_path: impl AsRef<Path>) -> Result<Self> {
+        /*
+        Load a (queries, dimension) .npy array whose rows follow the lines of a topics file, either
+        "qid\ttext" or only the query text
+        */
+        let (values, rows, columns) = parse_npy(&std::fs::read(embeddings_path)?)?;
+        let queries = std::fs::read_to_string(queries_path)?;
+        let queries: Vec<&str> = queries.lines().filter(|line| !line.trim().is_empty()).collect();
+
+        if queries.len() != rows {
+            return Err(Error::CorpusParse(format!("{} queries were given {} embeddings", queries.len(), rows)));
+        }
+
+        let mut encoder = Self { embeddings: Vec::with_capacity(rows), keys: HashMap::new(), dimension: 0 };
+        for (query, vector) in queries.iter().zip(values.chunks_exact(columns.max(1))) {
+            let keys = query.split('\t').map(str::to_string).collect();
+            encoder.push(keys, vector.to_vec())?;
+        }
+
+        Ok(encoder)
+    }
+
+    fn push(&mut self, keys: Vec<String>, vector: Vec<f32>) -> Result<()> {
+        if self.embeddings.is_empty() {
+            self.dimension = vector.len();
+        } else if vector.len() != self.dimension {
+            return Err(Error::DimensionMismatch { expected: self.dimension, actual: vector.len() });
+        }
+
+        for key in keys {
+            self.keys.insert(key, self.embeddings.len());
+        }
+        self.embeddings.push(vector);
+        Ok(())
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    pub fn len(&self) -> usize {
+        self.embeddings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.embeddings.is_empty()
+    }
+}
+
+impl QueryEncoder for PreEncodedQueryEncoder {
+    fn new(
+        model_name: &str,
+        _revision: &str,
+    ) -> Result<Self> {
+        Self::from_path(model_name)
+    }
+
+    fn encode(&self, queries: QueryType, _pooling: Pooling, normalize: bool) -> Result<Tensor> {
+        /*
+        Look up the embeddings of the queries, the pooling was applied when they were encoded
+        */
+        let queries = match queries {
+            QueryType::Query { query } => vec![query],
+            QueryType::Queries { query } => query,
         };
 
-        Ok(embeddings)
+        let mut values = Vec::with_capacity(queries.len() * self.dimension);
+        for query in &queries {
+            let index = self
+                .keys
+                .get(query)
+                .ok_or_else(|| Error::NotFound(format!("No pre-encoded embedding for the query {:?}", query)))?;
+            values.extend_from_slice(&self.embeddings[*index]);
+        }
+
+        let embeddings = Tensor::from_vec(values, (queries.len(), self.dimension), &Device::Cpu)?;
+        if normalize {
+            normalize_l2(&embeddings)
+        } else {
+            Ok(embeddings)
+        }
     }
 }
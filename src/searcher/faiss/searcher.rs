@@ -1,13 +1,15 @@
+use crate::encode::config::EncoderConfig;
+use crate::encode::pooling::Pooling;
 use crate::searcher::faiss::model::{AutoQueryEncoder, QueryEncoder, QueryType};
 
-use anyhow::Ok;
+use crate::error::{Error, Result};
 use faiss::index::io::read_index;
 use faiss::index::IndexImpl;
 use faiss::Index;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
 pub enum FaissSearchReturn {
@@ -16,10 +18,12 @@ pub enum FaissSearchReturn {
 }
 
 pub struct FaissSearcher {
-    query_encoder: AutoQueryEncoder,
+    query_encoder: Box<dyn QueryEncoder>,
     dimension: usize,
     index: IndexImpl,
     docids: Vec<String>,
+    pooling: Pooling,
+    normalize: bool,
 }
 
 #[derive(Debug)]
@@ -38,44 +42,70 @@ pub struct PRFDenseSearchResult {
 }
 
 impl FaissSearcher {
-    pub fn new(index_dir: String, query_encoder: AutoQueryEncoder, dimension: usize) -> Self {
+    pub fn new(index_dir: String, query_encoder: AutoQueryEncoder, dimension: usize) -> Result<Self> {
         /*
-        Create a new instance of FaissSearcher
+        Create a new instance of FaissSearcher, encoding queries with the EncoderConfig stored in the index if any
+        The dimension is the one of the query embeddings: the Matryoshka truncation dimension when the index was
+        written with one, and the unreduced dimension for PCA indexes, which reduce the queries themselves
          */
-        let index: IndexImpl = Self::load_index(&index_dir);
-        let docids: Vec<String> = Self::load_docids(&index_dir);
-        Self {
+        let query_encoder = match EncoderConfig::load(&index_dir)? {
+            Some(config) => query_encoder.with_config(config)?,
+            None => query_encoder,
+        };
+        Self::from_query_encoder(index_dir, Box::new(query_encoder), dimension)
+    }
+
+    pub fn from_query_encoder(index_dir: String, query_encoder: Box<dyn QueryEncoder>, dimension: usize) -> Result<Self> {
+        /*
+        Create a new instance of FaissSearcher over any QueryEncoder, e.g. a PreEncodedQueryEncoder that needs no model weights
+         */
+        let index: IndexImpl = Self::load_index(Path::new(&index_dir))?;
+        if index.d() as usize != dimension {
+            return Err(Error::DimensionMismatch {
+                expected: index.d() as usize,
+                actual: dimension,
+            });
+        }
+        let docids: Vec<String> = Self::load_docids(Path::new(&index_dir))?;
+        Ok(Self {
             query_encoder,
             dimension,
             index,
             docids,
-        }
+            pooling: Pooling::Cls,
+            normalize: false,
+        })
     }
 
-    fn load_index(index_dir: &String) -> IndexImpl {
+    pub fn with_pooling(mut self, pooling: Pooling, normalize: bool) -> Self {
+        /*
+        Set the pooling strategy and L2 normalization used to encode queries, defaults to unnormalized CLS pooling
+         */
+        self.pooling = pooling;
+        self.normalize = normalize;
+        self
+    }
+
+    pub(crate) fn load_index(index_dir: &Path) -> Result<IndexImpl> {
         /*
         Load a Faiss index from a directory
          */
-        let index_dir: PathBuf = PathBuf::from(index_dir);
         let index_path: PathBuf = index_dir.join("index");
-        let index: IndexImpl = read_index(index_path.as_path().display().to_string()).unwrap();
+        let index: IndexImpl = read_index(index_path.as_path().display().to_string())?;
 
-        index
+        Ok(index)
     }
 
-    fn load_docids(index_dir: &String) -> Vec<String> {
+    pub(crate) fn load_docids(index_dir: &Path) -> Result<Vec<String>> {
         /*
         Load a list of docids from a file
          */
-        let index_dir: PathBuf = PathBuf::from(index_dir);
         let docid_path: PathBuf = index_dir.join("docid");
-        let file = File::open(docid_path).map_err(|err| err.to_string());
-        let reader = BufReader::new(file.unwrap());
+        let file = File::open(docid_path)?;
+        let reader = BufReader::new(file);
 
-        reader
-            .lines()
-            .map(|l| l.expect("Could not parse line"))
-            .collect()
+        let docids = reader.lines().collect::<std::io::Result<Vec<String>>>()?;
+        Ok(docids)
     }
 
     pub fn search(
@@ -83,17 +113,22 @@ impl FaissSearcher {
         query: String,
         k: usize,
         return_vector: bool,
-    ) -> Result<FaissSearchReturn, anyhow::Error> {
+    ) -> Result<FaissSearchReturn> {
         /*
         Search a query and return the top k results
          */
         let query = QueryType::Query { query };
-        let emb_q = self.query_encoder.encode(query, "cls")?;
+        let emb_q = self.query_encoder.encode(query, self.pooling, self.normalize)?;
         let emb_q = emb_q.squeeze(0)?.to_vec1::<f32>()?;
 
 
-        assert_eq!(&emb_q.len(), &self.dimension);
-        let result = self.index.search(&emb_q, k).unwrap();
+        if emb_q.len() != self.dimension {
+            return Err(Error::DimensionMismatch {
+                expected: self.dimension,
+                actual: emb_q.len(),
+            });
+        }
+        let result = self.index.search(&emb_q, k)?;
 
         let scores = result.distances.iter();
         let indices = result.labels.iter();
@@ -106,11 +141,9 @@ impl FaissSearcher {
 
             Ok(FaissSearchReturn::PRFDense(result_iter.collect()))
         } else {
-            let result_iter = indices.zip(scores).map(|(x, y)| {
-                DenseSearchResult::new(
-                    self.docids[usize::try_from(x.get().unwrap()).unwrap()].clone(),
-                    *y,
-                )
+            // Faiss pads the results with -1 labels when fewer than k documents are found
+            let result_iter = indices.zip(scores).filter_map(|(x, y)| {
+                x.get().map(|x| DenseSearchResult::new(self.docids[x as usize].clone(), *y))
             });
 
             Ok(FaissSearchReturn::Dense(result_iter.collect()))
@@ -123,18 +156,23 @@ impl FaissSearcher {
         q_ids: Vec<String>,
         k: usize,
         _return_vector: bool,
-    ) -> Result<HashMap<String, FaissSearchReturn>, anyhow::Error> {
+    ) -> Result<HashMap<String, FaissSearchReturn>> {
         /*
         Search a batch of queries and return the top k results
          */
         let queries = QueryType::Queries { query: queries };
-        let emb_q = self.query_encoder.encode(queries, "cls")?;
+        let emb_q = self.query_encoder.encode(queries, self.pooling, self.normalize)?;
         let emb_q = emb_q.flatten_all()?.to_vec1::<f32>()?;
 
         let embedding_length = self.dimension * &q_ids.len();
-        assert_eq!(&emb_q.len(), &embedding_length);
+        if emb_q.len() != embedding_length {
+            return Err(Error::DimensionMismatch {
+                expected: embedding_length,
+                actual: emb_q.len(),
+            });
+        }
 
-        let result = self.index.search(&emb_q, k).unwrap();
+        let result = self.index.search(&emb_q, k)?;
 
         let scores_indices = result.distances.into_iter().zip(result.labels.into_iter());
         let scores_indices: Vec<(f32, faiss::Idx)> = scores_indices.collect();
@@ -142,9 +180,8 @@ impl FaissSearcher {
         let mut results: HashMap<String, FaissSearchReturn> = HashMap::new();
 
         for (i, doc_result) in scores_indices.chunks(k).enumerate() {
-            let index_result = doc_result.iter().map(|(score, idx)| {
-                let docid = self.docids[usize::try_from(idx.get().unwrap()).unwrap()].clone();
-                DenseSearchResult::new(docid, *score)
+            let index_result = doc_result.iter().filter_map(|(score, idx)| {
+                idx.get().map(|idx| DenseSearchResult::new(self.docids[idx as usize].clone(), *score))
             });
 
             let index_result = FaissSearchReturn::Dense(index_result.collect());
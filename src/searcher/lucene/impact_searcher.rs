@@ -0,0 +1,104 @@
+use crate::encode::vector_writer::quantize_weights;
+use crate::error::{Error, Result};
+use crate::searcher::lucene::model::SparseQueryEncoder;
+use crate::searcher::lucene::searcher::LuceneSearcherResult;
+use j4rs::{ClasspathEntry, Instance, InvocationArg, JavaClass, Jvm, JvmBuilder};
+use std::collections::HashMap;
+
+/// A LuceneImpactSearcher searches impact indexes, built from SPLADE or uniCOIL JsonVectorCollections with `-impact`
+/// It wraps Anserini's SimpleImpactSearcher, whose ImpactSimilarity scores a document by the sum over the query
+/// terms of the query weight times the stored impact, with no idf or length normalization
+/// It is designed to be a parallel of this Python Class
+/// https://github.com/castorini/pyserini/blob/45edec7e618db621339958c89fdff1d4a7a8cb90/pyserini/search/lucene/_impact_searcher.py
+pub struct LuceneImpactSearcher {
+    pub num_docs: usize,
+    jvm: Jvm,
+    searcher: Instance,
+    query_encoder: Option<Box<dyn SparseQueryEncoder>>,
+    quantization_factor: Option<f32>,
+}
+
+impl LuceneImpactSearcher {
+    pub fn new(index_dir: impl Into<String>) -> Result<Self> {
+        let entry = ClasspathEntry::new("resources/anserini-0.35.1-SNAPSHOT-fatjar.jar");
+        let jvm: Jvm = JvmBuilder::new().classpath_entry(entry).build()?;
+
+        let index_dir = InvocationArg::try_from(index_dir.into())?;
+        let searcher = jvm.create_instance("io.anserini.search.SimpleImpactSearcher", &[index_dir])?;
+
+        let num_docs = jvm.invoke(&searcher, "get_total_num_docs", InvocationArg::empty())?;
+        let num_docs: usize = jvm.to_rust(num_docs)?;
+
+        Ok(Self {
+            num_docs,
+            jvm,
+            searcher,
+            query_encoder: None,
+            quantization_factor: None,
+        })
+    }
+
+    pub fn with_query_encoder(mut self, query_encoder: Box<dyn SparseQueryEncoder>) -> Self {
+        /*
+        Set the encoder turning query strings into term weights, e.g. a SpladeQueryEncoder or a UniCoilQueryEncoder
+        */
+        self.query_encoder = Some(query_encoder);
+        self
+    }
+
+    pub fn with_quantization_factor(mut self, quantization_factor: f32) -> Self {
+        /*
+        Round the query weights to integer impacts, as Pyserini's query encoders do, instead of searching the raw weights
+        */
+        self.quantization_factor = Some(quantization_factor);
+        self
+    }
+
+    pub fn search(&self, query: &str, k: i32) -> Result<Vec<LuceneSearcherResult>> {
+        /*
+        Encode a query with the query encoder and search its term weights
+        */
+        let query_encoder = self
+            .query_encoder
+            .as_ref()
+            .ok_or_else(|| Error::Config("Searching query strings requires a query encoder".to_string()))?;
+
+        self.search_weights(&query_encoder.encode(query)?, k)
+    }
+
+    pub fn search_pretokenized(&self, query: &str, k: i32) -> Result<Vec<LuceneSearcherResult>> {
+        /*
+        Search a whitespace-separated bag of tokens where each occurrence of a token adds one to its weight,
+        the topic format of `-impact -pretokenized` runs written by weighted_query_string
+        */
+        let mut weights: HashMap<String, f32> = HashMap::new();
+        for token in query.split_whitespace() {
+            *weights.entry(token.to_string()).or_insert(0.0) += 1.0;
+        }
+
+        self.search_weights(&weights, k)
+    }
+
+    pub fn search_weights(&self, weights: &HashMap<String, f32>, k: i32) -> Result<Vec<LuceneSearcherResult>> {
+        /*
+        Search term weights as they are, tokens are matched verbatim against the indexed terms
+        */
+        let weights: HashMap<String, f32> = match self.quantization_factor {
+            Some(quantization_factor) => quantize_weights(weights, quantization_factor)
+                .into_iter()
+                .map(|(term, impact)| (term, impact as f32))
+                .collect(),
+            None => weights
+                .iter()
+                .filter(|(_, weight)| **weight > 0.0)
+                .map(|(term, weight)| (term.clone(), *weight))
+                .collect(),
+        };
+
+        let query = self.jvm.java_map(JavaClass::String, JavaClass::Float, weights)?;
+        let k = InvocationArg::try_from(k)?.into_primitive()?;
+        let results = self.jvm.invoke(&self.searcher, "search", &[query.into(), k])?;
+
+        Ok(self.jvm.to_rust(results)?)
+    }
+}
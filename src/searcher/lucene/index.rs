@@ -37,6 +37,14 @@ struct Args{
     /// Whether to store the raw documents
     #[arg(long)]
     store_raw: bool,
+
+    /// Whether the vector weights are impacts, e.g. for uniCOIL or SPLADE JsonVectorCollections
+    #[arg(long)]
+    impact: bool,
+
+    /// Whether the documents are already tokenized into wordpieces
+    #[arg(long)]
+    pretokenized: bool,
 }
 
 fn main() -> anyhow::Result<()>{
@@ -75,6 +83,14 @@ fn main() -> anyhow::Result<()>{
         java_args.push(InvocationArg::try_from("-storeRaw")?);
     }
 
+    if args.impact{
+        java_args.push(InvocationArg::try_from("-impact")?);
+    }
+
+    if args.pretokenized{
+        java_args.push(InvocationArg::try_from("-pretokenized")?);
+    }
+
     let arr_instance = jvm.create_java_array("java.lang.String", &java_args)?;
 
     let indexer =
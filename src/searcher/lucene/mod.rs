@@ -1,2 +1,4 @@
 pub mod searcher;
+pub mod impact_searcher;
 pub mod index;
+pub mod model;
@@ -0,0 +1,113 @@
+use crate::encode::auto::OutputModelType;
+use crate::encode::base::SparseDocumentEncoder;
+use crate::encode::config::EncoderConfig;
+use crate::encode::core::EncoderCore;
+use crate::encode::source::ModelSource;
+use crate::encode::unicoil::UniCoilDocumentEncoder;
+use crate::encode::vector_writer::quantize_weights;
+
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+
+/// A base trait for sparse query encoders producing term -> weight maps
+pub trait SparseQueryEncoder {
+    // instantiating a new SparseQueryEncoder instance
+    fn new(
+        model_name: &str,
+        revision: &str,
+    ) -> Result<Self>
+    where
+        Self: Sized;
+
+    // Encode a query into a term -> weight map
+    fn encode(&self, query: &str) -> Result<HashMap<String, f32>>;
+}
+
+/// A SpladeQueryEncoder for encoding queries into SPLADE term weights
+/// It is designed to be a parallel of this Python Class
+/// https://github.com/castorini/pyserini/blob/45edec7e618db621339958c89fdff1d4a7a8cb90/pyserini/encode/_splade.py
+pub struct SpladeQueryEncoder {
+    core: EncoderCore,
+}
+
+impl SpladeQueryEncoder {
+    pub fn from_source(source: &ModelSource) -> Result<Self> {
+        Self::from_sources(source, source)
+    }
+
+    pub fn from_sources(model_source: &ModelSource, tokenizer_source: &ModelSource) -> Result<Self> {
+        let core = EncoderCore::from_sources(model_source, tokenizer_source, OutputModelType::BertForMaskedLM)?;
+        Ok(Self { core })
+    }
+
+    pub fn with_config(mut self, config: EncoderConfig) -> Result<Self> {
+        self.core = self.core.with_config(config)?;
+        Ok(self)
+    }
+}
+
+impl SparseQueryEncoder for SpladeQueryEncoder {
+    fn new(
+        model_name: &str,
+        revision: &str,
+    ) -> Result<Self> {
+        Self::from_source(&ModelSource::new(model_name, revision))
+    }
+
+    fn encode(&self, query: &str) -> Result<HashMap<String, f32>> {
+        let tokens = self.core.tokenize_queries(&[query.to_string()])?;
+        let mut weights = self.core.term_weights(tokens)?;
+
+        weights.pop().ok_or(Error::Encoding("No weights were produced for the query".to_string()))
+    }
+}
+
+/// A UniCoilQueryEncoder for encoding queries into uniCOIL impact weights, with the same head as the documents
+/// It is designed to be a parallel of this Python Class
+/// https://github.com/castorini/pyserini/blob/45edec7e618db621339958c89fdff1d4a7a8cb90/pyserini/encode/_unicoil.py
+pub struct UniCoilQueryEncoder {
+    encoder: UniCoilDocumentEncoder,
+}
+
+impl UniCoilQueryEncoder {
+    pub fn from_source(source: &ModelSource) -> Result<Self> {
+        Ok(Self { encoder: UniCoilDocumentEncoder::from_source(source)? })
+    }
+
+    pub fn with_config(mut self, config: EncoderConfig) -> Result<Self> {
+        self.encoder = self.encoder.with_config(config)?;
+        Ok(self)
+    }
+}
+
+impl SparseQueryEncoder for UniCoilQueryEncoder {
+    fn new(
+        model_name: &str,
+        revision: &str,
+    ) -> Result<Self> {
+        Self::from_source(&ModelSource::new(model_name, revision))
+    }
+
+    fn encode(&self, query: &str) -> Result<HashMap<String, f32>> {
+        let mut weights = self.encoder.encode(&vec![query.to_string()], None)?;
+
+        weights.pop().ok_or(Error::Encoding("No weights were produced for the query".to_string()))
+    }
+}
+
+pub fn weighted_query_string(weights: &HashMap<String, f32>, quantization_factor: f32) -> String {
+    /*
+    Turn term weights into an Anserini weighted bag-of-words query, each term is repeated as many times as its
+    quantized weight, as in the pretokenized topics of impact indexes searched with `-impact -pretokenized`
+    The terms are wordpieces, search the string with LuceneImpactSearcher::search_pretokenized rather than
+    LuceneSearcher, whose analyzer would stem and split them
+    */
+    let mut impacts: Vec<(String, u32)> = quantize_weights(weights, quantization_factor).into_iter().collect();
+    impacts.sort();
+
+    impacts
+        .iter()
+        .flat_map(|(term, impact)| std::iter::repeat_n(term.as_str(), *impact as usize))
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
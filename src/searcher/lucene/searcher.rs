@@ -1,3 +1,4 @@
+use crate::error::Result;
 use j4rs::{ClasspathEntry, Instance, InvocationArg, JavaClass, Jvm, JvmBuilder};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -25,7 +26,7 @@ impl LuceneSearcher {
     pub fn new(
         index_dir: impl Into<String>,
         prebuilt_index_name: Option<String>,
-    ) -> Result<Self, anyhow::Error> {
+    ) -> Result<Self> {
         let entry = ClasspathEntry::new("resources/anserini-0.35.1-SNAPSHOT-fatjar.jar");
         let jvm: Jvm = JvmBuilder::new().classpath_entry(entry).build()?;
 
@@ -45,6 +46,43 @@ impl LuceneSearcher {
         })
     }
 
+    pub fn weighted_query(&self, weights: &HashMap<String, f32>, field: &str) -> Result<LuceneQuery> {
+        /*
+        Build a Lucene BooleanQuery matching any of the terms on the field, each term boosted by its weight
+        The boosts multiply the BM25 scores of this searcher, impact indexes are searched with LuceneImpactSearcher
+        */
+        let occur = self
+            .jvm
+            .static_class_field("org.apache.lucene.search.BooleanClause$Occur", "SHOULD")?;
+        let builder = self
+            .jvm
+            .create_instance("org.apache.lucene.search.BooleanQuery$Builder", InvocationArg::empty())?;
+
+        let mut terms: Vec<(&String, &f32)> = weights.iter().filter(|(_, weight)| **weight > 0.0).collect();
+        terms.sort_by(|a, b| a.0.cmp(b.0));
+
+        for (term, weight) in terms {
+            let term = self.jvm.create_instance(
+                "org.apache.lucene.index.Term",
+                &[InvocationArg::try_from(field)?, InvocationArg::try_from(term)?],
+            )?;
+            let term_query = self
+                .jvm
+                .create_instance("org.apache.lucene.search.TermQuery", &[term.into()])?;
+            let term_query = self.jvm.cast(&term_query, "org.apache.lucene.search.Query")?;
+            let boost_query = self.jvm.create_instance(
+                "org.apache.lucene.search.BoostQuery",
+                &[term_query.into(), InvocationArg::try_from(*weight)?.into_primitive()?],
+            )?;
+            let boost_query = self.jvm.cast(&boost_query, "org.apache.lucene.search.Query")?;
+            let occur = self.jvm.clone_instance(&occur)?;
+            self.jvm.invoke(&builder, "add", &[boost_query.into(), occur.into()])?;
+        }
+
+        let query = self.jvm.invoke(&builder, "build", InvocationArg::empty())?;
+        Ok(LuceneQuery::Instance(query))
+    }
+
     pub fn search(
         &self,
         q: LuceneQuery,
@@ -53,7 +91,7 @@ impl LuceneSearcher {
         fields: Option<HashMap<String, f32>>,
         _strip_segment_id: bool,
         _remove_dups: bool,
-    ) -> Result<Vec<LuceneSearcherResult>, anyhow::Error> {
+    ) -> Result<Vec<LuceneSearcherResult>> {
         let jfields: Option<Instance>;
         let hits: Vec<LuceneSearcherResult>;
         match fields {
@@ -89,9 +127,14 @@ impl LuceneSearcher {
                     hits = self.jvm.to_rust(results)?;
                 }
             }
-            LuceneQuery::Instance(_q) => {
-                // to be implemented
-                hits = Vec::new();
+            LuceneQuery::Instance(q) => {
+                // A Lucene Query carries its own fields, so the field boosts do not apply, and is scored with BM25
+                let k = InvocationArg::try_from(k)?.into_primitive()?;
+                let query = self.jvm.cast(&q, "org.apache.lucene.search.Query")?;
+                let results = self
+                    .jvm
+                    .invoke(&self.searcher, "search", &[query.into(), k])?;
+                hits = self.jvm.to_rust(results)?;
             }
         }
 
@@ -106,7 +149,7 @@ impl LuceneSearcher {
         threads: i32,
         _query_generator: Option<Instance>,
         fields: Option<HashMap<String, f32>>,
-    ) -> Result<HashMap<String, Vec<LuceneSearcherResult>>, anyhow::Error> {
+    ) -> Result<HashMap<String, Vec<LuceneSearcherResult>>> {
         let jfields: Option<Instance>;
         let hits: HashMap<String, Vec<LuceneSearcherResult>>;
 
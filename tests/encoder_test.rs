@@ -1,10 +1,25 @@
 #[cfg(test)]
 mod tests {
+    use candle_core::{DType, Device, Tensor};
+    use candle_nn::VarBuilder;
     use faiss::Index;
-    use rustserini::encode::auto::AutoDocumentEncoder;
-    use rustserini::encode::base::{DocumentEncoder, RepresentationWriter};
+    use rustserini::encode::auto::{build_model, load_var_builder, AutoDocumentEncoder, OutputModelType};
+    use rustserini::encode::base::{DocumentEncoder, RepresentationWriter, SparseDocumentEncoder};
+    use rustserini::encode::bge_m3::BgeM3Encoder;
+    use rustserini::encode::cache::EmbeddingCache;
+    use rustserini::encode::colbert::ColBertEncoder;
+    use rustserini::encode::config::{EncoderConfig, FieldJoin, TruncationSide, TruncationStrategy};
+    use rustserini::encode::pipeline::{budget_batches, encode_with_budget, EncodingPipeline};
+    use rustserini::encode::pooling::{truncate_embeddings, Pooling};
+    use rustserini::encode::registry::ModelRegistry;
+    use rustserini::encode::sentence_transformers::SentenceTransformerEncoder;
+    use rustserini::encode::source::{parse_device, ModelSource, WeightFiles};
+    use rustserini::encode::splade::SpladeDocumentEncoder;
+    use rustserini::encode::static_embedding::StaticEmbeddingEncoder;
+    use rustserini::encode::unicoil::UniCoilDocumentEncoder;
     use rustserini::encode::vector_writer::FaissRepresentationWriter;
     use rustserini::encode::vector_writer::{JsonlCollectionIterator, JsonlRepresentationWriter};
+    use rustserini::encode::vector_writer::{quantize_weights, JsonVectorCollectionWriter};
     use std::collections::HashMap;
     use std::time::Instant;
 
@@ -18,7 +33,7 @@ mod tests {
         let model_name = "bert-base-uncased";
         let revision = "refs/pr/70";
         let document_encoder: AutoDocumentEncoder =
-            AutoDocumentEncoder::new(model_name, revision);
+            AutoDocumentEncoder::new(model_name, revision)?;
         let start = Instant::now();
 
         let texts = vec![
@@ -26,7 +41,7 @@ mod tests {
             "And another sentence.".to_string(),
         ];
         let titles = vec!["Title 1".to_string(), "Title 2".to_string()];
-        let embeddings = document_encoder.encode(&texts, Some(&titles), "cls")?;
+        let embeddings = document_encoder.encode(&texts, Some(&titles), Pooling::Cls, false)?;
 
         let embeddings = embeddings.flatten_all()?;
         let embeddings = embeddings.to_vec1()?;
@@ -92,7 +107,7 @@ mod tests {
         let model_name = "castorini/mdpr-tied-pft-msmarco-ft-miracl-zh";
         let revision = "refs/pr/1";
         let document_encoder: AutoDocumentEncoder =
-            AutoDocumentEncoder::new(model_name, revision);
+            AutoDocumentEncoder::new(model_name, revision)?;
 
         let texts = vec![
             "Hello, I am a sentence!".to_string(),
@@ -102,7 +117,7 @@ mod tests {
             "Title 1".to_string(),
             "Title 2".to_string()
             ];
-        let embeddings = document_encoder.encode(&texts, Some(&titles), "cls")?;
+        let embeddings = document_encoder.encode(&texts, Some(&titles), Pooling::Cls, false)?;
 
         let embeddings = embeddings.flatten_all()?;
         let embeddings = embeddings.to_vec1()?;
@@ -157,10 +172,376 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_pooling_ignores_padding() -> anyhow::Result<()> {
+        let device = Device::Cpu;
+        let hidden_state = Tensor::new(
+            &[
+                [[1f32, 2.], [3., 4.], [5., 6.]],
+                [[1f32, 2.], [3., 4.], [100., 100.]],
+            ],
+            &device,
+        )?;
+        let attention_mask = Tensor::new(&[[1u32, 1, 1], [1, 1, 0]], &device)?;
+
+        let cls = Pooling::Cls.pool(&hidden_state, &attention_mask, false)?;
+        assert_eq!(cls.to_vec2::<f32>()?, vec![vec![1., 2.], vec![1., 2.]]);
+
+        let mean = Pooling::Mean.pool(&hidden_state, &attention_mask, false)?;
+        assert_eq!(mean.to_vec2::<f32>()?, vec![vec![3., 4.], vec![2., 3.]]);
+
+        let max = Pooling::Max.pool(&hidden_state, &attention_mask, false)?;
+        assert_eq!(max.to_vec2::<f32>()?, vec![vec![5., 6.], vec![3., 4.]]);
+
+        let last = Pooling::LastToken.pool(&hidden_state, &attention_mask, false)?;
+        assert_eq!(last.to_vec2::<f32>()?, vec![vec![5., 6.], vec![3., 4.]]);
+
+        let normalized = Pooling::LastToken.pool(&hidden_state, &attention_mask, true)?;
+        let norms = normalized.sqr()?.sum(1)?.to_vec1::<f32>()?;
+        assert!(norms.iter().all(|norm| (norm - 1.0).abs() < 1e-5));
+
+        assert!("attention".parse::<Pooling>().is_err());
+        assert_eq!("mean".parse::<Pooling>()?, Pooling::Mean);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_registry() -> anyhow::Result<()> {
+        let device = Device::Cpu;
+        let registry = ModelRegistry::default();
+
+        let config = r#"{"architectures": ["NotARealModel"], "model_type": "not-real"}"#;
+        let vb = VarBuilder::zeros(DType::F32, &device);
+        let error = registry
+            .load(vb, config, OutputModelType::BertModel)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("XLMRobertaModel"));
+
+        let config = r#"{
+            "model_type": "bert", "vocab_size": 10, "hidden_size": 8, "num_hidden_layers": 1,
+            "num_attention_heads": 2, "intermediate_size": 16, "hidden_act": "gelu",
+            "hidden_dropout_prob": 0.1, "max_position_embeddings": 16, "type_vocab_size": 2,
+            "initializer_range": 0.02, "layer_norm_eps": 1e-12, "pad_token_id": 0
+        }"#;
+        let vb = VarBuilder::zeros(DType::F32, &device);
+        let model = registry.load(vb, config, OutputModelType::BertModel)?;
+
+        let token_ids = Tensor::new(&[[1u32, 2, 3], [1, 2, 0]], &device)?;
+        let attention_mask = Tensor::new(&[[1u32, 1, 1], [1, 1, 0]], &device)?;
+        let hidden_state = model.forward(&token_ids, &token_ids.zeros_like()?, &attention_mask)?;
+        assert_eq!(hidden_state.dims(), &[2, 3, 8]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_device() -> anyhow::Result<()> {
+        assert!(parse_device("cpu")?.is_cpu());
+        assert!(parse_device("CPU")?.is_cpu());
+        assert!(parse_device("tpu").is_err());
+        assert!(parse_device("cuda:first").is_err());
+        if !candle_core::utils::cuda_is_available() {
+            assert!(parse_device("cuda:0").is_err());
+        }
+
+        let source = ModelSource::new("bert-base-uncased", "main").with_device(parse_device("cpu")?);
+        assert!(source.device.is_cpu());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_source_reports_missing_files() -> anyhow::Result<()> {
+        let model_dir = std::env::temp_dir().join("rustserini-local-model");
+        let _ = std::fs::remove_dir_all(&model_dir);
+        std::fs::create_dir_all(&model_dir)?;
+        std::fs::write(model_dir.join("config.json"), "{}")?;
+
+        let source = ModelSource::local(&model_dir);
+        assert_eq!(source.get("config.json")?, model_dir.join("config.json"));
+        let error = source.get("tokenizer.json").err().unwrap();
+        assert!(error.to_string().contains("tokenizer.json"));
+
+        let cache_dir = std::env::temp_dir().join("rustserini-empty-cache");
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        std::fs::create_dir_all(&cache_dir)?;
+        let source = ModelSource::new("bert-base-uncased", "main")
+            .offline(true)
+            .with_cache_dir(&cache_dir);
+        let error = source.get("model.safetensors").err().unwrap();
+        assert!(error.to_string().contains("model.safetensors"));
+        assert!(error.to_string().contains("offline"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sharded_prefixed_weights() -> anyhow::Result<()> {
+        let device = Device::Cpu;
+        let model_dir = std::env::temp_dir().join("rustserini-sharded-model");
+        let _ = std::fs::remove_dir_all(&model_dir);
+        std::fs::create_dir_all(&model_dir)?;
+
+        let word_embeddings = Tensor::ones((4, 2), DType::F32, &device)?;
+        let position_embeddings = Tensor::zeros((4, 2), DType::F32, &device)?;
+        candle_core::safetensors::save(
+            &HashMap::from([(
+                "ctx_encoder.bert_model.embeddings.word_embeddings.weight".to_string(),
+                word_embeddings,
+            )]),
+            model_dir.join("model-00001-of-00002.safetensors"),
+        )?;
+        candle_core::safetensors::save(
+            &HashMap::from([(
+                "ctx_encoder.bert_model.embeddings.position_embeddings.weight".to_string(),
+                position_embeddings,
+            )]),
+            model_dir.join("model-00002-of-00002.safetensors"),
+        )?;
+        std::fs::write(
+            model_dir.join("model.safetensors.index.json"),
+            r#"{"weight_map": {
+                "ctx_encoder.bert_model.embeddings.word_embeddings.weight": "model-00001-of-00002.safetensors",
+                "ctx_encoder.bert_model.embeddings.position_embeddings.weight": "model-00002-of-00002.safetensors"
+            }}"#,
+        )?;
+
+        let weights = ModelSource::local(&model_dir).weights()?;
+        match &weights {
+            WeightFiles::SafeTensors(shards) => assert_eq!(shards.len(), 2),
+            _ => panic!("Unexpected weight format"),
+        }
+
+        let vb = load_var_builder(&weights, DType::F32, &device)?;
+        assert!(vb.contains_tensor("embeddings.word_embeddings.weight"));
+        assert!(vb.contains_tensor("embeddings.position_embeddings.weight"));
+        assert_eq!(
+            vb.get((4, 2), "embeddings.word_embeddings.weight")?.sum_all()?.to_scalar::<f32>()?,
+            8.0
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_splade_document_encoder() -> anyhow::Result<()> {
+        let model_name = "naver/splade-cocondenser-ensembledistil";
+        let revision = "main";
+        let document_encoder: SpladeDocumentEncoder =
+            SpladeDocumentEncoder::new(model_name, revision)?;
+
+        let texts = vec![
+            "The manhattan project produced the first nuclear weapons.".to_string(),
+            "And another sentence.".to_string(),
+        ];
+        let term_weights = document_encoder.encode(&texts, None)?;
+
+        assert_eq!(term_weights.len(), 2);
+        assert!(term_weights[0].contains_key("manhattan"));
+        assert!(term_weights[0].values().all(|&weight| weight > 0.0));
+        assert!(!term_weights[1].contains_key("manhattan"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unicoil_document_encoder() -> anyhow::Result<()> {
+        let document_encoder = UniCoilDocumentEncoder::new("castorini/unicoil-msmarco-passage", "main")?;
+
+        let texts = vec![
+            "The manhattan project produced the first nuclear weapons.".to_string(),
+            "And another sentence.".to_string(),
+        ];
+        let term_weights = document_encoder.encode(&texts, None)?;
+
+        assert_eq!(term_weights.len(), 2);
+        assert!(term_weights[0].contains_key("manhattan"));
+        assert!(!term_weights[0].contains_key("[CLS]"));
+        assert!(!term_weights[1].contains_key("[PAD]"));
+        assert!(term_weights[0].values().all(|&weight| weight >= 0.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_vector_collection_writer() -> anyhow::Result<()> {
+        let weights = HashMap::from([
+            ("manhattan".to_string(), 2.345),
+            ("project".to_string(), 0.5),
+            ("the".to_string(), 0.001),
+        ]);
+        let impacts = quantize_weights(&weights, 100.0);
+        assert_eq!(impacts, HashMap::from([("manhattan".to_string(), 235), ("project".to_string(), 50)]));
+
+        let dir = std::env::temp_dir().join("rustserini-json-vector-collection");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        let mut writer = JsonVectorCollectionWriter::new(dir.to_str().unwrap());
+        writer.open_file()?;
+        let batch_info = HashMap::from([
+            ("id", vec!["doc0".to_string()]),
+            ("text", vec!["the manhattan project".to_string()]),
+        ]);
+        writer.write(&batch_info, &[weights])?;
+
+        let written = std::fs::read_to_string(dir.join("embeddings.jsonl"))?;
+        let record: serde_json::Value = serde_json::from_str(written.trim())?;
+        assert_eq!(record["id"], "doc0");
+        assert_eq!(record["vector"]["manhattan"], 235);
+        assert!(record["vector"].get("the").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_colbert_encoder() -> anyhow::Result<()> {
+        use rustserini::searcher::faiss::colbert::maxsim;
+
+        let encoder = ColBertEncoder::from_source(&ModelSource::new("colbert-ir/colbertv2.0", "main"))?;
+        let dimension = encoder.dimension();
+        assert_eq!(dimension, 128);
+
+        let queries = encoder.encode_queries(&["who led the manhattan project?".to_string()])?;
+        assert_eq!(queries[0].dims2()?, (32, dimension));
+        let norms = queries[0].sqr()?.sum(1)?.to_vec1::<f32>()?;
+        assert!(norms.iter().all(|norm| (norm - 1.0).abs() < 1e-4));
+
+        let texts = vec![
+            "The manhattan project was led by general Leslie Groves and J. Robert Oppenheimer.".to_string(),
+            "Bananas are rich in potassium.".to_string(),
+        ];
+        let documents = encoder.encode_documents(&texts, None)?;
+        assert_eq!(documents.len(), 2);
+        assert!(documents.iter().all(|document| document.dim(1).ok() == Some(dimension)));
+
+        let query = queries[0].flatten_all()?.to_vec1::<f32>()?;
+        let relevant = maxsim(&query, &documents[0].flatten_all()?.to_vec1::<f32>()?, dimension);
+        let irrelevant = maxsim(&query, &documents[1].flatten_all()?.to_vec1::<f32>()?, dimension);
+        assert!(relevant > irrelevant);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encoder_config_truncation() -> anyhow::Result<()> {
+        let source = ModelSource::new("bert-base-uncased", "refs/pr/70");
+        let tokenizer = tokenizers::Tokenizer::from_file(source.get("tokenizer.json")?)
+            .map_err(anyhow::Error::msg)?;
+
+        let config = EncoderConfig::new(16)
+            .with_truncation(TruncationStrategy::OnlySecond, TruncationSide::Right)
+            .with_max_title_length(2);
+        let joined = config.join_fields(
+            &tokenizer,
+            &["a short text".to_string()],
+            Some(&vec!["the manhattan project".to_string()]),
+        )?;
+        assert_eq!(joined, vec!["the manhattan a short text".to_string()]);
+
+        let config = config.with_truncation(TruncationStrategy::LongestFirst, TruncationSide::Left);
+        assert_eq!(config.truncate_field(&tokenizer, "the manhattan project", Some(1))?, "project");
+        assert!("longest-first".parse::<TruncationStrategy>().is_ok());
+        assert!("middle".parse::<TruncationSide>().is_err());
+
+        let document_encoder = AutoDocumentEncoder::new("bert-base-uncased", "refs/pr/70")?
+            .with_config(EncoderConfig::new(512))?;
+        let texts = vec!["word ".repeat(2000)];
+        let embeddings = document_encoder.encode(&texts, None, Pooling::Cls, false)?;
+        assert_eq!(embeddings.dims(), &[1, 768]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pair_encoding_token_type_ids() -> anyhow::Result<()> {
+        let source = ModelSource::new("bert-base-uncased", "refs/pr/70");
+        let tokenizer = tokenizers::Tokenizer::from_file(source.get("tokenizer.json")?)
+            .map_err(anyhow::Error::msg)?;
+
+        let texts = vec!["the first nuclear weapons".to_string()];
+        let titles = vec!["manhattan project".to_string()];
+
+        let config = EncoderConfig::default().with_field_join(FieldJoin::Pair);
+        let inputs = config.encode_inputs(&tokenizer, &texts, Some(&titles))?;
+        let tokens = config.tokenize(&tokenizer, inputs)?;
+        // [CLS] manhattan project [SEP] the first nuclear weapons [SEP]
+        assert_eq!(tokens[0].get_type_ids(), &[0, 0, 0, 0, 1, 1, 1, 1, 1]);
+
+        let config = EncoderConfig::default().with_field_join("separator: [SEP] ".parse()?);
+        let joined = config.join_fields(&tokenizer, &texts, Some(&titles))?;
+        assert_eq!(joined[0], "manhattan project [SEP] the first nuclear weapons");
+
+        let pair_encoder = AutoDocumentEncoder::new("bert-base-uncased", "refs/pr/70")?
+            .with_config(EncoderConfig::default().with_field_join(FieldJoin::Pair))?;
+        let space_encoder = AutoDocumentEncoder::new("bert-base-uncased", "refs/pr/70")?;
+        let pair = pair_encoder.encode(&texts, Some(&titles), Pooling::Cls, false)?;
+        let space = space_encoder.encode(&texts, Some(&titles), Pooling::Cls, false)?;
+        let difference = (pair - space)?.abs()?.sum_all()?.to_scalar::<f32>()?;
+        assert!(difference > 0.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encoder_config_templates() -> anyhow::Result<()> {
+        let config = EncoderConfig::default()
+            .with_query_template("query: {text}")
+            .with_document_template("passage: {text}");
+        assert_eq!(config.format_query("manhattan project"), "query: manhattan project");
+        assert_eq!(
+            config.format_document(Some("Manhattan"), "The project."),
+            "passage: Manhattan The project."
+        );
+
+        let config = config.with_document_template("title: {title} | text: {text}");
+        assert_eq!(
+            config.format_document(Some("Manhattan"), "The project."),
+            "title: Manhattan | text: The project."
+        );
+
+        // In pair mode the title is its own segment, so only the text goes through the template
+        let mut vocab = HashMap::new();
+        for (id, token) in ["[UNK]", "manhattan", "passage", ":", "project"].iter().enumerate() {
+            vocab.insert(token.to_string(), id as u32);
+        }
+        let model = tokenizers::models::wordlevel::WordLevel::builder()
+            .vocab(vocab)
+            .unk_token("[UNK]".to_string())
+            .build()
+            .map_err(anyhow::Error::msg)?;
+        let mut tokenizer = tokenizers::Tokenizer::new(model);
+        tokenizer.with_pre_tokenizer(Some(tokenizers::pre_tokenizers::whitespace::Whitespace {}));
+
+        let texts = vec!["project".to_string()];
+        let titles = vec!["manhattan".to_string()];
+        let pair_config = EncoderConfig::default()
+            .with_field_join(FieldJoin::Pair)
+            .with_document_template("passage: {text}");
+        let inputs = pair_config.encode_inputs(&tokenizer, &texts, Some(&titles))?;
+        let tokens = pair_config.tokenize(&tokenizer, inputs)?;
+        assert_eq!(tokens[0].get_tokens(), &["manhattan", "passage", ":", "project"]);
+        assert_eq!(tokens[0].get_type_ids(), &[0, 1, 1, 1]);
+
+        let pair_config = pair_config.with_document_template("title: {title} text: {text}");
+        assert!(pair_config.encode_inputs(&tokenizer, &texts, Some(&titles)).is_err());
+        assert!(pair_config.apply(&mut tokenizer, true).is_err());
+
+        let index_dir = std::env::temp_dir().join("rustserini-encoder-config");
+        let _ = std::fs::remove_dir_all(&index_dir);
+        std::fs::create_dir_all(&index_dir)?;
+        config.save(&index_dir)?;
+        assert_eq!(EncoderConfig::load(&index_dir)?, Some(config));
+        assert_eq!(EncoderConfig::load(std::env::temp_dir().join("rustserini-no-config"))?, None);
+
+        Ok(())
+    }
+
     #[test]
     fn test_json_representation_writer() -> anyhow::Result<()> {
         let path = "test";
-        let mut writer = JsonlRepresentationWriter::new(path, 3);
+        let mut writer = JsonlRepresentationWriter::new(path, 3)?;
         let _ = writer.open_file();
         let mut batch_info = HashMap::new();
         batch_info.insert("id", vec!["0".to_string(), "1".to_string()]);
@@ -188,8 +569,8 @@ mod tests {
     #[test]
     fn test_faiss_representation_writer() -> anyhow::Result<()> {
         let path = "test";
-        let mut writer = FaissRepresentationWriter::new(path, 3);
-        let _ = writer.init_index(3, "Flat");
+        let mut writer = FaissRepresentationWriter::new(path, 3)?;
+        writer.init_index(3, "Flat")?;
         let _ = writer.open_file();
     
         let mut batch_info = HashMap::new();
@@ -220,6 +601,44 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_faiss_pca_writer() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("rustserini-pca-index");
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path)?;
+        let mut writer = FaissRepresentationWriter::new(path.to_str().unwrap(), 8)?.with_training_sample(32);
+        writer.init_index(8, "PCA4,Flat")?;
+        writer.open_file()?;
+
+        // The PCA is trained once 32 embeddings were written, the rest are added as they come
+        for batch in 0..5 {
+            let ids: Vec<String> = (0..10).map(|i| format!("{}", batch * 10 + i)).collect();
+            let batch_info = HashMap::from([("id", ids.clone()), ("text", ids)]);
+            let mut embeddings: Vec<f32> = (0..80).map(|i| ((batch * 80 + i) as f32 * 0.37).sin()).collect();
+            writer.write(&batch_info, &mut embeddings)?;
+        }
+        writer.save_index()?;
+
+        assert!(writer.index.is_trained());
+        assert_eq!(writer.index.ntotal(), 50);
+        // Queries keep their 8 dimensions, the index reduces them itself
+        assert_eq!(writer.index.d(), 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matryoshka_truncation() -> anyhow::Result<()> {
+        let embeddings = Tensor::new(&[[3.0f32, 4.0, 12.0], [1.0, 0.0, 5.0]], &Device::Cpu)?;
+
+        let truncated = truncate_embeddings(&embeddings, 2, true)?;
+        assert_eq!(truncated.to_vec2::<f32>()?, vec![vec![0.6, 0.8], vec![1.0, 0.0]]);
+        assert_eq!(truncate_embeddings(&embeddings, 2, false)?.to_vec2::<f32>()?, vec![vec![3.0, 4.0], vec![1.0, 0.0]]);
+        assert!(truncate_embeddings(&embeddings, 4, true).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_jsonl_collection_iterator() -> anyhow::Result<()> {
         let path = "tests/test_files".to_string();
@@ -241,4 +660,576 @@ mod tests {
 
         Ok(())
     }
+
+    /// Embeds a document as [number of words, position of its id in the corpus]
+    struct WordCountEncoder;
+
+    impl DocumentEncoder for WordCountEncoder {
+        fn new(_model_name: &str, _revision: &str) -> rustserini::Result<Self> {
+            Ok(WordCountEncoder)
+        }
+
+        fn encode(
+            &self,
+            texts: &Vec<String>,
+            _titles: Option<&Vec<String>>,
+            _pooling: Pooling,
+            _normalize: bool,
+        ) -> rustserini::Result<Tensor> {
+            let embeddings: Vec<f32> = texts
+                .iter()
+                .flat_map(|text| {
+                    let words: Vec<&str> = text.split_whitespace().collect();
+                    let position: f32 = words[0].parse().unwrap();
+                    vec![words.len() as f32, position]
+                })
+                .collect();
+            Ok(Tensor::from_vec(embeddings, (texts.len(), 2), &Device::Cpu)?)
+        }
+    }
+
+    #[derive(Default)]
+    struct MemoryWriter {
+        ids: Vec<String>,
+        embeddings: Vec<f32>,
+    }
+
+    impl RepresentationWriter for MemoryWriter {
+        fn write(&mut self, batch_info: &HashMap<&str, Vec<String>>, embedding: &mut Vec<f32>) -> rustserini::Result<()> {
+            self.ids.extend(batch_info["id"].clone());
+            self.embeddings.extend(embedding.iter());
+            Ok(())
+        }
+
+        fn new(_path: &str, _dimension: u32) -> rustserini::Result<Self> {
+            Ok(MemoryWriter::default())
+        }
+
+        fn open_file(&mut self) -> rustserini::Result<()> {
+            Ok(())
+        }
+
+        fn save_index(&mut self) -> rustserini::Result<()> {
+            Ok(())
+        }
+
+        fn init_index(&mut self, _dim: u32, _index_type: &str) -> rustserini::Result<()> {
+            Ok(())
+        }
+
+        fn save_docids(&mut self) -> rustserini::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_encoding_pipeline_keeps_docid_order() -> anyhow::Result<()> {
+        let corpus_dir = std::env::temp_dir().join("rustserini-pipeline-corpus");
+        let _ = std::fs::remove_dir_all(&corpus_dir);
+        std::fs::create_dir_all(&corpus_dir)?;
+        let corpus: Vec<String> = (0..23)
+            .map(|i| {
+                let text = format!("{} {}", i, "word ".repeat((i * 7) % 11));
+                format!(r#"{{"id": "doc{}", "contents": "{}"}}"#, i, text.trim())
+            })
+            .collect();
+        std::fs::write(corpus_dir.join("corpus.jsonl"), corpus.join("\n"))?;
+
+        let mut iterator =
+            JsonlCollectionIterator::new(vec!["contents".to_string()], "id".to_string(), "\n".to_string(), 4);
+        iterator.load(corpus_dir.display().to_string())?;
+
+        let mut writer = MemoryWriter::default();
+        let pipeline = EncodingPipeline::new(3, 4).with_buckets(2);
+        let encoded = pipeline.run(&mut iterator, &WordCountEncoder, &mut writer)?;
+
+        assert_eq!(encoded, 23);
+        let ids: Vec<String> = (0..23).map(|i| format!("doc{}", i)).collect();
+        assert_eq!(writer.ids, ids);
+        for (i, embedding) in writer.embeddings.chunks(2).enumerate() {
+            assert_eq!(embedding, &[(1 + (i * 7) % 11) as f32, i as f32]);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_embedding_cache_skips_unchanged_documents() -> anyhow::Result<()> {
+        let corpus_dir = std::env::temp_dir().join("rustserini-cache-corpus");
+        let cache_dir = std::env::temp_dir().join("rustserini-embedding-cache");
+        let _ = std::fs::remove_dir_all(&corpus_dir);
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        std::fs::create_dir_all(&corpus_dir)?;
+
+        let write_corpus = |edited: usize| -> anyhow::Result<()> {
+            let corpus: Vec<String> = (0..10)
+                .map(|i| {
+                    let words = if i == edited { 9 } else { i % 4 };
+                    let text = format!("{} {}", i, "word ".repeat(words));
+                    format!(r#"{{"id": "doc{}", "contents": "{}"}}"#, i, text.trim())
+                })
+                .collect();
+            Ok(std::fs::write(corpus_dir.join("corpus.jsonl"), corpus.join("\n"))?)
+        };
+        let source = ModelSource::new("word-count", "v1");
+        let config = EncoderConfig::default();
+
+        let mut runs = Vec::new();
+        for edited in [usize::MAX, usize::MAX, 3] {
+            write_corpus(edited)?;
+            let mut iterator =
+                JsonlCollectionIterator::new(vec!["contents".to_string()], "id".to_string(), "\n".to_string(), 4);
+            iterator.load(corpus_dir.display().to_string())?;
+
+            let cache = EmbeddingCache::open(&cache_dir, &source, &source, Pooling::Cls, false, &config)?;
+            let pipeline = EncodingPipeline::new(3, 2).with_cache(cache);
+            let mut writer = MemoryWriter::default();
+            pipeline.run(&mut iterator, &WordCountEncoder, &mut writer)?;
+
+            let cache = pipeline.cache().unwrap();
+            runs.push((cache.hits(), cache.misses(), writer.embeddings));
+        }
+
+        // A fresh cache encodes everything, an unchanged corpus nothing, and an edit only the edited document
+        assert_eq!((runs[0].0, runs[0].1), (0, 10));
+        assert_eq!((runs[1].0, runs[1].1), (10, 0));
+        assert_eq!((runs[2].0, runs[2].1), (9, 1));
+        assert_eq!(runs[0].2, runs[1].2);
+        assert_eq!(runs[2].2[6..8], [10.0, 3.0]);
+
+        // Another pooling or another tokenizer does not share the cached embeddings
+        let cache = EmbeddingCache::open(&cache_dir, &source, &source, Pooling::Mean, false, &config)?;
+        assert!(cache.is_empty());
+        let tokenizer_source = ModelSource::new("word-count", "v2");
+        let cache = EmbeddingCache::open(&cache_dir, &source, &tokenizer_source, Pooling::Cls, false, &config)?;
+        assert!(cache.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_embedding_cache_recovers_from_truncated_record() -> anyhow::Result<()> {
+        let cache_dir = std::env::temp_dir().join("rustserini-truncated-cache");
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        std::fs::create_dir_all(&cache_dir)?;
+
+        let source = ModelSource::new("word-count", "v1");
+        let config = EncoderConfig::default();
+        let open = || EmbeddingCache::open(&cache_dir, &source, &source, Pooling::Cls, false, &config);
+
+        let cache = open()?;
+        cache.insert(1, &[1.0, 2.0])?;
+        cache.insert(2, &[3.0, 4.0])?;
+        cache.flush()?;
+        let path = cache.path().to_path_buf();
+        drop(cache);
+
+        // An interrupted run leaves the header and half the values of a third record
+        let mut bytes = std::fs::read(&path)?;
+        bytes.extend_from_slice(&3u128.to_le_bytes());
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&5.0f32.to_le_bytes());
+        std::fs::write(&path, bytes)?;
+
+        let cache = open()?;
+        assert_eq!(cache.len(), 2);
+        cache.insert(3, &[5.0, 6.0])?;
+        cache.insert(4, &[7.0, 8.0])?;
+        cache.flush()?;
+        drop(cache);
+
+        let cache = open()?;
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.get(1), Some(vec![1.0, 2.0]));
+        assert_eq!(cache.get(3), Some(vec![5.0, 6.0]));
+        assert_eq!(cache.get(4), Some(vec![7.0, 8.0]));
+        assert_eq!(std::fs::metadata(&path)?.len(), 4 * (16 + 4 + 2 * 4));
+
+        Ok(())
+    }
+
+    /// Fails with the given error on batches of more than two documents
+    struct SmallBatchEncoder(&'static str);
+
+    impl DocumentEncoder for SmallBatchEncoder {
+        fn new(_model_name: &str, _revision: &str) -> rustserini::Result<Self> {
+            Ok(SmallBatchEncoder("DriverError(CUDA_ERROR_OUT_OF_MEMORY, \"out of memory\")"))
+        }
+
+        fn encode(
+            &self,
+            texts: &Vec<String>,
+            titles: Option<&Vec<String>>,
+            pooling: Pooling,
+            normalize: bool,
+        ) -> rustserini::Result<Tensor> {
+            if texts.len() > 2 {
+                return Err(candle_core::Error::msg(self.0).into());
+            }
+            WordCountEncoder.encode(texts, titles, pooling, normalize)
+        }
+    }
+
+    #[test]
+    fn test_token_budget_batching() -> anyhow::Result<()> {
+        let lengths = vec![2, 8, 3, 4, 20];
+        let order = vec![0, 2, 3, 1, 4];
+        let batches = budget_batches(&order, &lengths, 12, 16);
+        assert_eq!(batches, vec![vec![0, 2, 3], vec![1], vec![4]]);
+        assert_eq!(budget_batches(&order, &lengths, 100, 2), vec![vec![0, 2], vec![3, 1], vec![4]]);
+
+        let texts: Vec<String> = (0..5).map(|i| format!("{} {}", i, "word ".repeat(i * 2))).collect();
+        let encoder = SmallBatchEncoder::new("", "")?;
+        let embeddings = encode_with_budget(&encoder, &texts, None, Pooling::Cls, false, 1000)?;
+        for (i, embedding) in embeddings.iter().enumerate() {
+            assert_eq!(embedding, &vec![(1 + i * 2) as f32, i as f32]);
+        }
+
+        // Other errors are not retried, even when they mention an allocation
+        let encoder = SmallBatchEncoder("Failed to allocate the tokenizer padding");
+        assert!(encode_with_budget(&encoder, &texts, None, Pooling::Cls, false, 1000).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reduced_precision_and_quantized_bert() -> anyhow::Result<()> {
+        use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+        use candle_transformers::models::bert::{BertModel, Config};
+
+        let device = Device::Cpu;
+        let model_dir = std::env::temp_dir().join("rustserini-quantized-bert");
+        let _ = std::fs::remove_dir_all(&model_dir);
+        std::fs::create_dir_all(&model_dir)?;
+
+        let config = r#"{
+            "architectures": ["BertModel"], "model_type": "bert", "vocab_size": 64, "hidden_size": 64,
+            "num_hidden_layers": 2, "num_attention_heads": 4, "intermediate_size": 128, "hidden_act": "gelu",
+            "hidden_dropout_prob": 0.0, "max_position_embeddings": 32, "type_vocab_size": 2,
+            "initializer_range": 0.02, "layer_norm_eps": 1e-12, "pad_token_id": 0
+        }"#;
+        std::fs::write(model_dir.join("config.json"), config)?;
+
+        let varmap = candle_nn::VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        BertModel::load(vb, &serde_json::from_str::<Config>(config)?)?;
+        varmap.save(model_dir.join("model.safetensors"))?;
+
+        // Unquantized f32 tensors in the GGUF file should reproduce the safetensors model exactly
+        let tensors = varmap.data().lock().unwrap();
+        let qtensors = tensors
+            .iter()
+            .map(|(name, var)| Ok((name.clone(), QTensor::quantize(var.as_tensor(), GgmlDType::F32)?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let qtensors: Vec<(&str, &QTensor)> = qtensors.iter().map(|(name, tensor)| (name.as_str(), tensor)).collect();
+        let mut file = std::fs::File::create(model_dir.join("model-f32.gguf"))?;
+        gguf_file::write(&mut file, &[], &qtensors)?;
+
+        let token_ids = Tensor::new(&[[2u32, 5, 9, 3], [2, 7, 0, 0]], &device)?;
+        let token_type_ids = token_ids.zeros_like()?;
+        let attention_mask = Tensor::new(&[[1u32, 1, 1, 1], [1, 1, 0, 0]], &device)?;
+
+        let registry = ModelRegistry::default();
+        let source = ModelSource::local(&model_dir);
+        let full = build_model(&source, OutputModelType::BertModel, &registry)?
+            .forward(&token_ids, &token_type_ids, &attention_mask)?;
+
+        let half = build_model(&source.clone().with_dtype(DType::F16), OutputModelType::BertModel, &registry)?
+            .forward(&token_ids, &token_type_ids, &attention_mask)?;
+        assert_eq!(half.dtype(), DType::F32);
+        let difference = (half - &full)?.abs()?.max_all()?.to_scalar::<f32>()?;
+        assert!(difference < 1e-1);
+
+        // A bf16 checkpoint is upcast, so it only differs by the rounding of its weights
+        let bf16_dir = std::env::temp_dir().join("rustserini-bf16-bert");
+        let _ = std::fs::remove_dir_all(&bf16_dir);
+        std::fs::create_dir_all(&bf16_dir)?;
+        std::fs::write(bf16_dir.join("config.json"), config)?;
+        let bf16_tensors = tensors
+            .iter()
+            .map(|(name, var)| Ok((name.clone(), var.as_tensor().to_dtype(DType::BF16)?)))
+            .collect::<anyhow::Result<HashMap<String, Tensor>>>()?;
+        candle_core::safetensors::save(&bf16_tensors, bf16_dir.join("model.safetensors"))?;
+        let bf16 = build_model(&ModelSource::local(&bf16_dir).with_dtype(DType::BF16), OutputModelType::BertModel, &registry)?
+            .forward(&token_ids, &token_type_ids, &attention_mask)?;
+        assert_eq!(bf16.dtype(), DType::F32);
+        let difference = (bf16 - &full)?.abs()?.max_all()?.to_scalar::<f32>()?;
+        assert!(difference < 1e-1);
+
+        let quantized = build_model(&source.clone().with_weights_file("model-f32.gguf"), OutputModelType::BertModel, &registry)?
+            .forward(&token_ids, &token_type_ids, &attention_mask)?;
+        let difference = (quantized - &full)?.abs()?.max_all()?.to_scalar::<f32>()?;
+        assert!(difference < 1e-4);
+
+        // Q8_0 matrices stay quantized in QMatMul, embeddings and norms are kept in f32 as llama.cpp does
+        let qtensors = tensors
+            .iter()
+            .map(|(name, var)| {
+                let dtype = match var.as_tensor().rank() == 2 && !name.contains("embeddings") {
+                    true => GgmlDType::Q8_0,
+                    false => GgmlDType::F32,
+                };
+                Ok((name.clone(), QTensor::quantize(var.as_tensor(), dtype)?))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let qtensors: Vec<(&str, &QTensor)> = qtensors.iter().map(|(name, tensor)| (name.as_str(), tensor)).collect();
+        let mut file = std::fs::File::create(model_dir.join("model-q8_0.gguf"))?;
+        gguf_file::write(&mut file, &[], &qtensors)?;
+
+        let quantized = build_model(&source.with_weights_file("model-q8_0.gguf"), OutputModelType::BertModel, &registry)?
+            .forward(&token_ids, &token_type_ids, &attention_mask)?;
+        let difference = (quantized - &full)?.abs()?.max_all()?.to_scalar::<f32>()?;
+        assert!(difference > 0.0 && difference < 1e-1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sentence_transformer_encoder() -> anyhow::Result<()> {
+        // all-MiniLM-L6-v2 is Transformer -> mean Pooling -> Normalize, capped at 256 tokens
+        let source = ModelSource::new("sentence-transformers/all-MiniLM-L6-v2", "main");
+        let encoder = SentenceTransformerEncoder::from_source(&source)?;
+        assert_eq!(encoder.pooling(), &[Pooling::Mean]);
+        assert!(encoder.normalizes());
+
+        let texts = vec!["The Manhattan Project produced the first nuclear weapons".to_string()];
+        let embeddings = encoder.encode(&texts, None, Pooling::Cls, false)?;
+        assert_eq!(embeddings.dims(), &[1, 384]);
+        let norm = embeddings.sqr()?.sum_all()?.sqrt()?.to_scalar::<f32>()?;
+        assert!((norm - 1.0).abs() < 1e-5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sentence_transformer_dense_modules() -> anyhow::Result<()> {
+        use candle_transformers::models::bert::{BertModel, Config};
+        use tokenizers::models::wordlevel::WordLevel;
+        use tokenizers::pre_tokenizers::whitespace::Whitespace;
+        use tokenizers::processors::template::TemplateProcessing;
+
+        let device = Device::Cpu;
+        let model_dir = std::env::temp_dir().join("rustserini-sentence-transformer");
+        let _ = std::fs::remove_dir_all(&model_dir);
+        std::fs::create_dir_all(model_dir.join("1_Pooling"))?;
+        std::fs::create_dir_all(model_dir.join("2_Dense"))?;
+        std::fs::create_dir_all(model_dir.join("3_Dense"))?;
+
+        // Transformer: a tiny random BERT and a word level tokenizer
+        let config = r#"{
+            "architectures": ["BertModel"], "model_type": "bert", "vocab_size": 16, "hidden_size": 32,
+            "num_hidden_layers": 2, "num_attention_heads": 4, "intermediate_size": 64, "hidden_act": "gelu",
+            "hidden_dropout_prob": 0.0, "max_position_embeddings": 32, "type_vocab_size": 2,
+            "initializer_range": 0.02, "layer_norm_eps": 1e-12, "pad_token_id": 0
+        }"#;
+        std::fs::write(model_dir.join("config.json"), config)?;
+        let varmap = candle_nn::VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let bert = BertModel::load(vb, &serde_json::from_str::<Config>(config)?)?;
+        varmap.save(model_dir.join("model.safetensors"))?;
+
+        let vocab = ["[PAD]", "[UNK]", "[CLS]", "[SEP]", "the", "manhattan", "project", "first", "nuclear", "weapons"]
+            .iter()
+            .enumerate()
+            .map(|(id, token)| (token.to_string(), id as u32))
+            .collect();
+        let model = WordLevel::builder()
+            .vocab(vocab)
+            .unk_token("[UNK]".to_string())
+            .build()
+            .map_err(anyhow::Error::msg)?;
+        let mut tokenizer = tokenizers::Tokenizer::new(model);
+        tokenizer.with_pre_tokenizer(Some(Whitespace {}));
+        let template = TemplateProcessing::builder()
+            .try_single("[CLS] $A [SEP]")
+            .map_err(anyhow::Error::msg)?
+            .special_tokens(vec![("[CLS]", 2), ("[SEP]", 3)])
+            .build()?;
+        tokenizer.with_post_processor(Some(template));
+        tokenizer.save(model_dir.join("tokenizer.json"), false).map_err(anyhow::Error::msg)?;
+
+        // Pooling: [CLS] and mean concatenated, then a Tanh Dense with a bias, an Identity Dense without, and Normalize
+        std::fs::write(
+            model_dir.join("modules.json"),
+            r#"[
+                {"idx": 0, "name": "0", "path": "", "type": "sentence_transformers.models.Transformer"},
+                {"idx": 1, "name": "1", "path": "1_Pooling", "type": "sentence_transformers.models.Pooling"},
+                {"idx": 2, "name": "2", "path": "2_Dense", "type": "sentence_transformers.models.Dense"},
+                {"idx": 3, "name": "3", "path": "3_Dense", "type": "sentence_transformers.models.Dense"},
+                {"idx": 4, "name": "4", "path": "4_Normalize", "type": "sentence_transformers.models.Normalize"}
+            ]"#,
+        )?;
+        std::fs::write(model_dir.join("sentence_bert_config.json"), r#"{"max_seq_length": 16}"#)?;
+        std::fs::write(
+            model_dir.join("1_Pooling/config.json"),
+            r#"{"word_embedding_dimension": 32, "pooling_mode_cls_token": true, "pooling_mode_mean_tokens": true}"#,
+        )?;
+        std::fs::write(
+            model_dir.join("2_Dense/config.json"),
+            r#"{"in_features": 64, "out_features": 16, "bias": true, "activation_function": "torch.nn.modules.activation.Tanh"}"#,
+        )?;
+        std::fs::write(
+            model_dir.join("3_Dense/config.json"),
+            r#"{"in_features": 16, "out_features": 8, "bias": false, "activation_function": "torch.nn.modules.linear.Identity"}"#,
+        )?;
+        let weight = Tensor::randn(0f32, 0.2, (16, 64), &device)?;
+        let bias = Tensor::randn(0f32, 0.2, 16, &device)?;
+        let projection = Tensor::randn(0f32, 0.5, (8, 16), &device)?;
+        candle_core::safetensors::save(
+            &HashMap::from([("linear.weight".to_string(), weight.clone()), ("linear.bias".to_string(), bias.clone())]),
+            model_dir.join("2_Dense/model.safetensors"),
+        )?;
+        candle_core::safetensors::save(
+            &HashMap::from([("linear.weight".to_string(), projection.clone())]),
+            model_dir.join("3_Dense/model.safetensors"),
+        )?;
+
+        let encoder = SentenceTransformerEncoder::from_source(&ModelSource::local(&model_dir))?;
+        assert_eq!(encoder.pooling(), &[Pooling::Cls, Pooling::Mean]);
+        assert!(encoder.normalizes());
+
+        // The texts have different lengths, so the batch is padded
+        let texts = vec!["the manhattan project".to_string(), "the first nuclear weapons of the project".to_string()];
+        let embeddings = encoder.encode(&texts, None, Pooling::Max, false)?.to_vec2::<f32>()?;
+
+        // What SentenceTransformer.encode computes, one unpadded text at a time
+        let matvec = |matrix: &Vec<Vec<f32>>, vector: &[f32]| -> Vec<f32> {
+            matrix.iter().map(|row| row.iter().zip(vector).map(|(w, x)| w * x).sum()).collect()
+        };
+        let (weight, bias, projection) = (weight.to_vec2::<f32>()?, bias.to_vec1::<f32>()?, projection.to_vec2::<f32>()?);
+        for (text, embedding) in texts.iter().zip(&embeddings) {
+            let ids = tokenizer.encode(text.as_str(), true).map_err(anyhow::Error::msg)?.get_ids().to_vec();
+            let token_ids = Tensor::new(ids.as_slice(), &device)?.unsqueeze(0)?;
+            let hidden = bert.forward(&token_ids, &token_ids.zeros_like()?, None)?.squeeze(0)?.to_vec2::<f32>()?;
+
+            let mut features = hidden[0].clone();
+            features.extend((0..32).map(|i| hidden.iter().map(|token| token[i]).sum::<f32>() / hidden.len() as f32));
+            let features: Vec<f32> = matvec(&weight, &features).iter().zip(&bias).map(|(x, b)| (x + b).tanh()).collect();
+            let features = matvec(&projection, &features);
+            let norm = features.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+            assert_eq!(embedding.len(), 8);
+            for (value, expected) in embedding.iter().zip(&features) {
+                assert!((value - expected / norm).abs() < 1e-4);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_onnx_backend() -> anyhow::Result<()> {
+        // The ONNX export of the repository runs through the same tokenization and pooling as the candle weights
+        let source = ModelSource::new("sentence-transformers/all-MiniLM-L6-v2", "main");
+        let onnx_source = source.clone().with_weights_file("onnx/model.onnx");
+        assert!(matches!(onnx_source.weights()?, WeightFiles::Onnx(_)));
+
+        let texts = vec!["The Manhattan Project produced the first nuclear weapons".to_string(), "Short text".to_string()];
+        let expected = AutoDocumentEncoder::from_source(&source)?.encode(&texts, None, Pooling::Mean, true)?;
+        let embeddings = AutoDocumentEncoder::from_source(&onnx_source)?.encode(&texts, None, Pooling::Mean, true)?;
+
+        let difference = (embeddings - expected)?.abs()?.max_all()?.to_scalar::<f32>()?;
+        assert!(difference < 1e-4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_local_onnx_export() -> anyhow::Result<()> {
+        // An Optimum export keeps its graph in onnx/ and may come without config.json
+        let model_dir = std::env::temp_dir().join("rustserini-onnx-export");
+        let _ = std::fs::remove_dir_all(&model_dir);
+        std::fs::create_dir_all(model_dir.join("onnx"))?;
+        std::fs::write(model_dir.join("onnx/model.onnx"), "not an onnx graph")?;
+        std::fs::write(model_dir.join("tokenizer.json"), "{}")?;
+
+        let source = ModelSource::local(&model_dir);
+        match source.weights()? {
+            WeightFiles::Onnx(filename) => assert_eq!(filename, model_dir.join("onnx/model.onnx")),
+            weights => panic!("Expected the ONNX export, found {:?}", weights),
+        }
+
+        // The graph is parsed without looking for config.json
+        let error = build_model(&source, OutputModelType::BertModel, &ModelRegistry::default()).err().unwrap();
+        assert!(error.to_string().contains("Could not load the ONNX model"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_static_embedding_encoder() -> anyhow::Result<()> {
+        // word2vec text format, with its "count dimension" header
+        let dir = std::env::temp_dir().join("rustserini-static-embeddings");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        let vectors = dir.join("vectors.txt");
+        std::fs::write(&vectors, "3 2\nthe 1.0 1.0\ncat 4.0 0.0\nmat 0.0 2.0\n")?;
+
+        let encoder = StaticEmbeddingEncoder::from_word_vectors(&vectors, true)?;
+        assert_eq!(encoder.dimension(), 2);
+
+        let texts = vec!["The cat, the MAT!".to_string(), "unknown words".to_string()];
+        let embeddings = DocumentEncoder::encode(&encoder, &texts, None, Pooling::Mean, false)?.to_vec2::<f32>()?;
+        assert_eq!(embeddings, vec![vec![1.5, 1.0], vec![0.0, 0.0]]);
+        assert_eq!(encoder.token_lengths(&texts, None)?, vec![4, 0]);
+
+        // SIF down-weights "the", the most frequent word
+        let encoder = StaticEmbeddingEncoder::from_word_vectors(&vectors, true)?.with_sif(1e-3);
+        let embeddings = DocumentEncoder::encode(&encoder, &vec!["the cat".to_string()], None, Pooling::Mean, false)?;
+        let embedding = embeddings.to_vec2::<f32>()?;
+        assert!(embedding[0][0] > 2.5);
+
+        // Titles and queries follow the EncoderConfig like the transformer encoders
+        use rustserini::searcher::faiss::model::{QueryEncoder, QueryType};
+
+        let config = EncoderConfig::default()
+            .with_field_join(FieldJoin::Separator(" mat ".to_string()))
+            .with_query_template("the {text}");
+        let encoder = StaticEmbeddingEncoder::from_word_vectors(&vectors, true)?.with_config(config)?;
+        let titles = vec!["cat".to_string()];
+        let embeddings = DocumentEncoder::encode(&encoder, &vec!["cat".to_string()], Some(&titles), Pooling::Mean, false)?;
+        assert_eq!(embeddings.to_vec2::<f32>()?, vec![vec![8.0 / 3.0, 2.0 / 3.0]]);
+        let query = QueryType::Query { query: "cat".to_string() };
+        let embeddings = QueryEncoder::encode(&encoder, query, Pooling::Mean, false)?;
+        assert_eq!(embeddings.to_vec2::<f32>()?, vec![vec![2.5, 0.5]]);
+
+        let config = EncoderConfig::default().with_field_join(FieldJoin::Pair);
+        assert!(StaticEmbeddingEncoder::from_word_vectors(&vectors, true)?.with_config(config).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bge_m3_encoder() -> anyhow::Result<()> {
+        let encoder = BgeM3Encoder::from_source(&ModelSource::new("BAAI/bge-m3", "main"))?;
+        let texts = vec![
+            "The Manhattan Project produced the first nuclear weapons".to_string(),
+            "Paris is the capital of France".to_string(),
+        ];
+
+        let documents = encoder.encode_documents(&texts, None)?;
+        assert_eq!(documents.dense.dims(), &[2, 1024]);
+        assert_eq!(encoder.dense_dimension()?, documents.dense.dim(1)?);
+        assert_eq!(documents.sparse.len(), 2);
+        assert!(documents.sparse[0].keys().all(|token| token != "<s>" && token != "</s>"));
+        assert_eq!(documents.multi_vector[0].dim(1)?, 1024);
+
+        // All three representations rank the relevant document first
+        let query = encoder.encode_queries(&["who built the first atomic bomb".to_string()])?;
+        let dense_scores = query.dense.matmul(&documents.dense.t()?)?.squeeze(0)?.to_vec1::<f32>()?;
+        assert!(dense_scores[0] > dense_scores[1]);
+
+        let lexical_score = |document: &HashMap<String, f32>| -> f32 {
+            query.sparse[0].iter().map(|(token, weight)| weight * document.get(token).unwrap_or(&0.0)).sum()
+        };
+        assert!(lexical_score(&documents.sparse[0]) >= lexical_score(&documents.sparse[1]));
+
+        let maxsim = |document: &Tensor| -> anyhow::Result<f32> {
+            let similarities = query.multi_vector[0].matmul(&document.t()?)?;
+            Ok(similarities.max(1)?.sum_all()?.to_scalar::<f32>()?)
+        };
+        assert!(maxsim(&documents.multi_vector[0])? > maxsim(&documents.multi_vector[1])?);
+
+        Ok(())
+    }
 }
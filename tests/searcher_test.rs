@@ -1,8 +1,16 @@
 #[cfg(test)]
 mod tests {
-    use rustserini::searcher::faiss::model::{AutoQueryEncoder, QueryEncoder};
+    use rustserini::encode::auto::AutoDocumentEncoder;
+    use rustserini::encode::base::DocumentEncoder;
+    use rustserini::encode::pooling::Pooling;
+    use rustserini::encode::source::ModelSource;
+    use rustserini::searcher::faiss::model::{AutoQueryEncoder, QueryEncoder, QueryType};
     use rustserini::searcher::faiss::searcher::{FaissSearchReturn, FaissSearcher};
+    use j4rs::{ClasspathEntry, InvocationArg, Jvm, JvmBuilder};
+    use rustserini::searcher::lucene::impact_searcher::LuceneImpactSearcher;
+    use rustserini::searcher::lucene::model::weighted_query_string;
     use rustserini::searcher::lucene::searcher::{LuceneQuery, LuceneSearcher};
+    use std::collections::HashMap;
     use std::time::Instant;
 
     #[test]
@@ -11,13 +19,13 @@ mod tests {
         let model_name = "castorini/mdpr-tied-pft-msmarco-ft-miracl-zh";
         let revision = "refs/pr/1";
         let query_encoder: AutoQueryEncoder =
-            AutoQueryEncoder::new(model_name, revision);
+            AutoQueryEncoder::new(model_name, revision)?;
     
         let mut searcher = FaissSearcher::new(
             "corpus/msmarco-passage-mini/pyserini".to_string(),
             query_encoder,
             768 as usize,
-        );
+        )?;
     
         let result = searcher.search(
             "did scientific minds lead to the success of the manhattan project".to_string(),
@@ -38,19 +46,52 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_dpr_encoders_with_separate_tokenizer() -> anyhow::Result<()> {
+        let tokenizer_source = ModelSource::new("bert-base-uncased", "refs/pr/70");
+        let query_encoder = AutoQueryEncoder::from_sources(
+            &ModelSource::new("facebook/dpr-question_encoder-single-nq-base", "main"),
+            &tokenizer_source,
+        )?;
+        let document_encoder = AutoDocumentEncoder::from_sources(
+            &ModelSource::new("facebook/dpr-ctx_encoder-single-nq-base", "main"),
+            &tokenizer_source,
+        )?;
+
+        let query = QueryType::Query {
+            query: "who led the manhattan project".to_string(),
+        };
+        let query = query_encoder.encode(query, Pooling::Cls, false)?;
+        let documents = document_encoder.encode(
+            &vec![
+                "The Manhattan Project was led by the physicist J. Robert Oppenheimer.".to_string(),
+                "Bananas are a good source of potassium.".to_string(),
+            ],
+            None,
+            Pooling::Cls,
+            false,
+        )?;
+        assert_eq!(query.dims(), &[1, 768]);
+
+        let scores = query.matmul(&documents.t()?)?.squeeze(0)?.to_vec1::<f32>()?;
+        assert!(scores[0] > scores[1]);
+
+        Ok(())
+    }
+
     #[test]
     fn test_faiss_batch_searcher() -> anyhow::Result<()> {
         let start = Instant::now();
         let model_name = "castorini/mdpr-tied-pft-msmarco-ft-miracl-zh";
         let revision = "refs/pr/1";
         let query_encoder: AutoQueryEncoder =
-            AutoQueryEncoder::new(model_name, revision);
+            AutoQueryEncoder::new(model_name, revision)?;
     
         let mut searcher = FaissSearcher::new(
             "corpus/msmarco-passage-mini/pyserini".to_string(),
             query_encoder,
             768 as usize,
-        );
+        )?;
     
         let result = searcher.batch_search(
             vec![
@@ -77,6 +118,173 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_colbert_maxsim() {
+        use rustserini::searcher::faiss::colbert::maxsim;
+
+        // Two 2-dimensional query vectors against three document vectors
+        let query = vec![1.0, 0.0, 0.0, 1.0];
+        let document = vec![0.5, 0.5, 0.9, 0.1, 0.2, 0.7];
+
+        assert!((maxsim(&query, &document, 2) - (0.9 + 0.7)).abs() < 1e-6);
+        assert_eq!(maxsim(&query, &[], 2), 0.0);
+    }
+
+    #[test]
+    fn test_pre_encoded_query_encoder() -> anyhow::Result<()> {
+        use rustserini::searcher::faiss::model::PreEncodedQueryEncoder;
+
+        let dir = std::env::temp_dir().join("rustserini-pre-encoded-queries");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+
+        let jsonl = dir.join("embedding.jsonl");
+        std::fs::write(
+            &jsonl,
+            "{\"id\": \"1\", \"contents\": \"first query\", \"vector\": [3.0, 4.0]}\n{\"id\": 2, \"text\": \"second query\", \"vector\": [1.0, 0.0]}\n",
+        )?;
+        let encoder = PreEncodedQueryEncoder::from_path(&dir)?;
+        assert_eq!((encoder.len(), encoder.dimension()), (2, 2));
+
+        let queries = QueryType::Queries { query: vec!["second query".to_string(), "1".to_string()] };
+        let embeddings = encoder.encode(queries, Pooling::Cls, true)?.to_vec2::<f32>()?;
+        assert_eq!(embeddings, vec![vec![1.0, 0.0], vec![0.6, 0.8]]);
+        let unknown = encoder.encode(QueryType::Query { query: "unknown".to_string() }, Pooling::Cls, false);
+        assert!(matches!(unknown, Err(rustserini::error::Error::NotFound(_))));
+
+        // A (2, 3) float32 array in the .npy format, next to its topics
+        let header = "{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3), }";
+        let mut npy = b"\x93NUMPY\x01\x00".to_vec();
+        npy.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        npy.extend_from_slice(header.as_bytes());
+        for value in [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0] {
+            npy.extend_from_slice(&value.to_le_bytes());
+        }
+        std::fs::write(dir.join("queries.npy"), npy)?;
+        std::fs::write(dir.join("queries.tsv"), "q1\tfirst query\nq2\tsecond query\n")?;
+
+        let encoder = PreEncodedQueryEncoder::from_path(dir.join("queries.npy"))?;
+        let embedding = encoder.encode(QueryType::Query { query: "q2".to_string() }, Pooling::Cls, false)?;
+        assert_eq!(embedding.to_vec2::<f32>()?, vec![vec![4.0, 5.0, 6.0]]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_candidates() {
+        use rustserini::rerank::{sort_candidates, Candidate};
+
+        let candidates = vec![
+            Candidate::new("0", "first", 10.0),
+            Candidate::new("1", "second", 9.0),
+            Candidate::new("2", "third", 8.0),
+        ];
+        let reranked = sort_candidates(candidates, &[-1.0, 3.0, -1.0]);
+
+        let docids: Vec<&str> = reranked.iter().map(|candidate| candidate.docid.as_str()).collect();
+        assert_eq!(docids, vec!["1", "0", "2"]);
+        assert_eq!(reranked[0].score, 3.0);
+    }
+
+    #[test]
+    fn test_cross_encoder_reranker() -> anyhow::Result<()> {
+        use rustserini::rerank::{Candidate, CrossEncoderReranker, Reranker};
+
+        let reranker = CrossEncoderReranker::from_source(&ModelSource::new("cross-encoder/ms-marco-MiniLM-L-6-v2", "main"))?;
+        let candidates = vec![
+            Candidate::new("0", "The Manhattan Project was a research and development undertaking during World War II that produced the first nuclear weapons.", 0.0),
+            Candidate::new("1", "The presence of communication amid scientific minds was equally important to the success of the Manhattan Project as scientific intellect was.", 0.0),
+        ];
+
+        let reranked = reranker.rerank("did scientific minds lead to the success of the manhattan project", candidates)?;
+
+        assert_eq!(reranked[0].docid, "1");
+        assert!(reranked[0].score > reranked[1].score);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mono_t5_reranker() -> anyhow::Result<()> {
+        use rustserini::rerank::{Candidate, MonoT5Reranker, Reranker};
+
+        let reranker = MonoT5Reranker::from_sources(
+            &ModelSource::new("castorini/monot5-base-msmarco", "main"),
+            &ModelSource::new("t5-base", "main"),
+        )?;
+        let candidates = vec![
+            Candidate::new("0", "The Manhattan Project was a research and development undertaking during World War II that produced the first nuclear weapons.", 0.0),
+            Candidate::new("1", "The presence of communication amid scientific minds was equally important to the success of the Manhattan Project as scientific intellect was.", 0.0),
+        ];
+
+        let reranked = reranker.rerank("did scientific minds lead to the success of the manhattan project", candidates)?;
+
+        assert_eq!(reranked[0].docid, "1");
+        assert!(reranked.iter().all(|candidate| candidate.score <= 0.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duo_t5_aggregation() -> anyhow::Result<()> {
+        use rustserini::rerank::t5::{rerank_top_k, sym_sum};
+        use rustserini::rerank::Candidate;
+
+        // Document 1 beats everything with 0.9, document 0 beats document 2 with 0.6
+        let probabilities = [[0.0, 0.2, 0.6], [0.9, 0.0, 0.9], [0.3, 0.1, 0.0]];
+        let scores = sym_sum(3, |i, j| Ok(probabilities[i][j]))?;
+        // Every ordered pair hands out one point, 6 in total
+        let expected = [
+            0.2 + 0.6 + (1.0 - 0.9) + (1.0 - 0.3),
+            0.9 + 0.9 + (1.0 - 0.2) + (1.0 - 0.1),
+            0.3 + 0.1 + (1.0 - 0.6) + (1.0 - 0.9),
+        ];
+        for (score, expected) in scores.iter().zip(expected) {
+            assert!((score - expected).abs() < 1e-6);
+        }
+
+        let candidates: Vec<Candidate> = ["a", "b", "c", "d", "e"]
+            .iter()
+            .enumerate()
+            .map(|(i, text)| Candidate::new(i.to_string(), *text, 5.0 - i as f32))
+            .collect();
+        let reranked = rerank_top_k(candidates, 3, |texts| {
+            assert_eq!(texts, &["a", "b", "c"]);
+            Ok(vec![1.0, 3.0, 2.0])
+        })?;
+        let docids: Vec<&str> = reranked.iter().map(|candidate| candidate.docid.as_str()).collect();
+        assert_eq!(docids, vec!["1", "2", "0", "3", "4"]);
+        assert_eq!(reranked[3].score, 2.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duo_t5_reranker() -> anyhow::Result<()> {
+        use rustserini::rerank::{Candidate, DuoT5Reranker, Reranker};
+
+        let reranker = DuoT5Reranker::from_sources(
+            &ModelSource::new("castorini/duot5-base-msmarco", "main"),
+            &ModelSource::new("t5-base", "main"),
+        )?
+        .with_top_k(2);
+        let candidates = vec![
+            Candidate::new("0", "The Manhattan Project was a research and development undertaking during World War II that produced the first nuclear weapons.", 3.0),
+            Candidate::new("1", "The presence of communication amid scientific minds was equally important to the success of the Manhattan Project as scientific intellect was.", 2.0),
+            Candidate::new("2", "Nuclear weapons are explosive devices.", 1.0),
+        ];
+
+        let reranked = reranker.rerank("did scientific minds lead to the success of the manhattan project", candidates)?;
+
+        // The two compared candidates share 2 points, the third keeps its first stage score
+        let docids: Vec<&str> = reranked.iter().map(|candidate| candidate.docid.as_str()).collect();
+        assert_eq!(docids, vec!["1", "0", "2"]);
+        assert!((reranked[0].score + reranked[1].score - 2.0).abs() < 1e-4);
+        assert_eq!(reranked[2].score, 1.0);
+
+        Ok(())
+    }
+
     #[test]
     fn test_lucene_searcher() {
         let search_instance = LuceneSearcher::new(
@@ -99,6 +307,109 @@ mod tests {
         assert_eq!(result[0].docid, "0")
     }
 
+    #[test]
+    fn test_weighted_query_string() {
+        let weights = HashMap::from([
+            ("mat".to_string(), 0.02),
+            ("cat".to_string(), 0.031),
+            ("the".to_string(), 0.004),
+        ]);
+
+        assert_eq!(weighted_query_string(&weights, 100.0), "cat cat cat mat mat");
+        assert_eq!(weighted_query_string(&HashMap::new(), 100.0), "");
+    }
+
+    fn build_index(name: &str, collection: &str, docs: &[&str], flags: &[&str]) -> anyhow::Result<String> {
+        let corpus_dir = std::env::temp_dir().join(format!("{}-corpus", name));
+        let index_dir = std::env::temp_dir().join(format!("{}-index", name));
+        let _ = std::fs::remove_dir_all(&corpus_dir);
+        let _ = std::fs::remove_dir_all(&index_dir);
+        std::fs::create_dir_all(&corpus_dir)?;
+        std::fs::write(corpus_dir.join("docs.jsonl"), docs.join("\n"))?;
+
+        let entry = ClasspathEntry::new("resources/anserini-0.35.1-SNAPSHOT-fatjar.jar");
+        let jvm: Jvm = JvmBuilder::new().classpath_entry(entry).build()?;
+
+        let mut java_args = vec![];
+        for arg in [
+            "-collection",
+            collection,
+            "-input",
+            corpus_dir.to_str().unwrap(),
+            "-index",
+            index_dir.to_str().unwrap(),
+            "-generator",
+            "DefaultLuceneDocumentGenerator",
+            "-threads",
+            "1",
+        ]
+        .iter()
+        .chain(flags)
+        {
+            java_args.push(InvocationArg::try_from(arg.to_string())?);
+        }
+        let arr_instance = jvm.create_java_array("java.lang.String", &java_args)?;
+        jvm.invoke_static("io.anserini.index.IndexCollection", "main", &[InvocationArg::from(arr_instance)])?;
+
+        Ok(index_dir.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_lucene_weighted_query_search() -> anyhow::Result<()> {
+        let index_dir = build_index(
+            "rustserini-weighted-query",
+            "JsonCollection",
+            &[
+                r#"{"id": "0", "contents": "cat cat mat"}"#,
+                r#"{"id": "1", "contents": "dog"}"#,
+            ],
+            &[],
+        )?;
+        let searcher = LuceneSearcher::new(index_dir, None)?;
+
+        let weights = HashMap::from([("dog".to_string(), 10.0), ("cat".to_string(), 0.1)]);
+        let query = searcher.weighted_query(&weights, "contents")?;
+        let result = searcher.search(query, 10, None, None, false, false)?;
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].docid, "1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lucene_impact_searcher() -> anyhow::Result<()> {
+        let index_dir = build_index(
+            "rustserini-impact",
+            "JsonVectorCollection",
+            &[
+                r#"{"id": "0", "contents": "", "vector": {"cat": 3, "mat": 2}}"#,
+                r###"{"id": "1", "contents": "", "vector": {"cat": 1, "dog": 5, "##s": 2}}"###,
+            ],
+            &["-impact", "-pretokenized"],
+        )?;
+        let searcher = LuceneImpactSearcher::new(index_dir)?;
+        assert_eq!(searcher.num_docs, 2);
+
+        /* The scores are the plain dot products of the query weights and the stored impacts */
+        let weights = HashMap::from([("cat".to_string(), 0.5), ("mat".to_string(), 2.0)]);
+        let result = searcher.search_weights(&weights, 10)?;
+        assert_eq!(result[0].docid, "0");
+        assert!((result[0].score - (0.5 * 3.0 + 2.0 * 2.0)).abs() < 1e-4);
+        assert_eq!(result[1].docid, "1");
+        assert!((result[1].score - 0.5).abs() < 1e-4);
+
+        /* Wordpieces are matched verbatim, the English analyzer would have stripped "##s" down to "s" */
+        let weights = HashMap::from([("##s".to_string(), 0.01), ("dog".to_string(), 0.02)]);
+        let pretokenized = weighted_query_string(&weights, 100.0);
+        let result = searcher.search_pretokenized(&pretokenized, 10)?;
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].docid, "1");
+        assert!((result[0].score - (1.0 * 2.0 + 2.0 * 5.0)).abs() < 1e-4);
+
+        Ok(())
+    }
+
     #[test]
     fn test_batch_lucene_searcher() {
         let search_instance = LuceneSearcher::new(